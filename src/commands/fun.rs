@@ -5,7 +5,6 @@ use serenity::framework::standard::Args;
 use serenity::framework::standard::{macros::command, CommandResult};
 use serenity::model::prelude::*;
 use serenity::prelude::*;
-use std::collections::HashMap;
 
 use serde::Deserialize;
 
@@ -28,7 +27,7 @@ pub async fn xkcd(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     let mut response_msg = msg.channel_id.say(&ctx, "Fetching XKCD").await?;
     let mut url = String::from("https://xkcd.com/");
 
-    if args.len() == 0 {
+    if args.is_empty() {
         url.push_str("info.0.json");
     } else if args.len() == 1 {
         url.push_str(&format!("{}/info.0.json", args.current().unwrap_or("")));
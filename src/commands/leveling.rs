@@ -1,49 +1,162 @@
-use crate::util::leveling::get_user_level;
-use crate::RedisConnection;
+use crate::util::activity::{get_activity_days, render_activity_chart, sparkline, MAX_DAYS};
+use crate::util::card::{render_rank_card, CardTheme, RankCard};
+use crate::util::data::{leveling_config, storage};
+use crate::util::leveling::{get_current_streak, get_level_progress, progress_bar};
+use crate::util::periods::{get_current_season, period_id, season_id, Period};
+use crate::util::storage::{Storage, StorageResult};
 
+use chrono::Utc;
+use serenity::builder::{CreateComponents, CreateEmbed};
 use serenity::framework::standard::Args;
-use serenity::framework::standard::{macros::command, CommandResult};
-use serenity::futures::StreamExt;
-use serenity::http::CacheHttp;
-use serenity::model::guild::MembersIter;
+use serenity::framework::standard::{macros::command, CommandError, CommandResult};
+use serenity::http::AttachmentType;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
-use tracing::{error, info};
-
-use std::cmp::Ordering;
+use serenity::model::interactions::{
+    message_component::ButtonStyle, InteractionResponseType,
+};
+use serenity::utils::ArgumentConvert;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::error;
 
 #[command]
-#[description = "Gets your level and such"]
+#[description = "Gets your level and such, or someone else's. Add `--card` for an image"]
 #[only_in(guilds)]
-#[num_args(0)]
-pub async fn rank(ctx: &Context, msg: &Message) -> CommandResult {
-    let mut bot_data = ctx.data.write().await;
-    let mut redis_conn = bot_data.get_mut::<RedisConnection>().unwrap();
+#[usage("[member] [--card]")]
+pub async fn rank(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let storage = storage(ctx).await;
+    let config = leveling_config(ctx).await;
+
+    let mut as_card = false;
+    let mut query = Vec::new();
+    for arg in args.raw() {
+        if arg == "--card" {
+            as_card = true;
+        } else {
+            query.push(arg);
+        }
+    }
+    let query = query.join(" ");
+
+    let user = if query.is_empty() {
+        msg.author.clone()
+    } else {
+        match Member::convert(ctx, msg.guild_id, Some(msg.channel_id), &query).await {
+            Ok(member) => member.user,
+            Err(_) => {
+                msg.channel_id
+                    .say(&ctx.http, "Could not find that member")
+                    .await?;
+                return Ok(());
+            }
+        }
+    };
 
-    let level_data = match get_user_level(msg.author.id.0, redis_conn) {
+    let guild_id = msg.guild_id.unwrap().0;
+    let level_data = match storage.get_user_level(guild_id, user.id.0).await {
         Ok(data) => data,
         Err(e) => {
             msg.channel_id
-                .say(&ctx.http, format!("Could not get your points: {:?}", e))
-                .await;
+                .say(&ctx.http, format!("Could not get points: {:?}", e))
+                .await?;
             return Ok(());
         }
     };
-    let avatar_url = match msg.author.avatar_url() {
+    let rank = storage.get_user_rank(guild_id, user.id.0).await?;
+    let streak = get_current_streak(
+        storage.as_ref(),
+        &config,
+        guild_id,
+        user.id.0,
+        &level_data,
+        Utc::now(),
+    )
+    .await?;
+    let (progress, level_size) = get_level_progress(&config.curve, level_data.xp);
+
+    if as_card {
+        let theme = storage
+            .get_user_setting(user.id.0, "card_theme")
+            .await?
+            .and_then(|t| CardTheme::parse(&t))
+            .unwrap_or_default();
+        let name = match msg.guild_id.unwrap().member(ctx, user.id).await {
+            Ok(member) => member.display_name().to_string(),
+            Err(_) => user.name.clone(),
+        };
+        let avatar = match fetch_avatar(&user).await {
+            Ok(avatar) => Some(avatar),
+            Err(e) => {
+                error!("Could not fetch avatar for {}: {:?}", user.id, e);
+                None
+            }
+        };
+        let level = level_data.level;
+        let png = tokio::task::spawn_blocking(move || {
+            render_rank_card(&RankCard {
+                name: &name,
+                level,
+                rank: rank.map(|pos| pos + 1),
+                progress,
+                level_size,
+                avatar: avatar.as_ref(),
+                theme,
+            })
+        })
+        .await??;
+
+        msg.channel_id
+            .send_files(
+                &ctx.http,
+                vec![AttachmentType::Bytes {
+                    data: png.into(),
+                    filename: String::from("rank.png"),
+                }],
+                |m| m,
+            )
+            .await?;
+
+        return Ok(());
+    }
+
+    let position = match rank {
+        Some(pos) => format!("#{}", pos + 1),
+        None => String::from("Unranked"),
+    };
+    let avatar_url = match user.avatar_url() {
         Some(url) => url,
-        None => msg.author.default_avatar_url(),
+        None => user.default_avatar_url(),
     };
     msg.channel_id
         .send_message(&ctx.http, |m| {
-            m.embed(|mut e| {
+            m.embed(|e| {
                 e.author(|m| {
                     m.icon_url(avatar_url);
-                    m.name(msg.author.name.clone());
+                    m.name(user.name.clone());
                     m
                 });
                 e.field("Messages", level_data.msg_count.to_string(), true);
                 e.field("XP", level_data.xp.to_string(), true);
                 e.field("Level", level_data.level.to_string(), true);
+                e.field("Rank", position, true);
+                e.field("Voice minutes", level_data.voice_minutes.to_string(), true);
+                e.field(
+                    "Streak",
+                    format!("{} days (best {})", streak.current, streak.longest),
+                    true,
+                );
+                e.field(
+                    format!("Progress to level {}", level_data.level + 1),
+                    format!(
+                        "{} {}/{} ({} XP to go)",
+                        progress_bar(progress, level_size, 20),
+                        progress,
+                        level_size,
+                        level_size - progress
+                    ),
+                    false,
+                );
                 e
             });
             m
@@ -53,117 +166,458 @@ pub async fn rank(ctx: &Context, msg: &Message) -> CommandResult {
     Ok(())
 }
 
+/// Downloads a user's avatar as a PNG
+async fn fetch_avatar(user: &User) -> CommandResult<image::DynamicImage> {
+    let url = user.face().replace(".webp", ".png");
+    let bytes = reqwest::get(url).await?.bytes().await?;
+
+    Ok(image::load_from_memory(&bytes)?)
+}
+
+#[command]
+#[description = "Sets the colours of your rank card"]
+#[num_args(1)]
+#[usage("<dark | light | crimson | ocean>")]
+pub async fn cardtheme(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let theme = match CardTheme::parse(args.rest()) {
+        Some(theme) => theme,
+        None => {
+            let names: Vec<&str> = CardTheme::ALL.iter().map(|t| t.name()).collect();
+            msg.channel_id
+                .say(&ctx.http, format!("Pick one of: {}", names.join(", ")))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    storage(ctx)
+        .await
+        .set_user_setting(msg.author.id.0, "card_theme", theme.name())
+        .await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Charts daily XP and messages for you, someone else or the whole server. Add `--text` to skip the image"]
+#[only_in(guilds)]
+#[usage("[member | server] [days] [--text]")]
+pub async fn activity(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let storage = storage(ctx).await;
+
+    let mut as_text = false;
+    let mut days = 30;
+    let mut query = Vec::new();
+    for arg in args.raw() {
+        if arg == "--text" {
+            as_text = true;
+        } else if let Some(n) = arg.parse::<i64>().ok().filter(|n| (1..=MAX_DAYS).contains(n)) {
+            // Anything longer is a user ID rather than a number of days
+            days = n;
+        } else {
+            query.push(arg);
+        }
+    }
+    let query = query.join(" ");
+
+    let guild_id = msg.guild_id.unwrap();
+    let (user_id, title) = if query == "server" {
+        let name = match guild_id.name(ctx).await {
+            Some(name) => name,
+            None => String::from("Server"),
+        };
+        (None, name)
+    } else if query.is_empty() {
+        (Some(msg.author.id.0), msg.author.name.clone())
+    } else {
+        match Member::convert(ctx, msg.guild_id, Some(msg.channel_id), &query).await {
+            Ok(member) => (Some(member.user.id.0), member.display_name().to_string()),
+            Err(_) => {
+                msg.channel_id
+                    .say(&ctx.http, "Could not find that member")
+                    .await?;
+                return Ok(());
+            }
+        }
+    };
+    let title = format!("{} over the last {} days", title, days);
+
+    let history = get_activity_days(
+        guild_id.0,
+        user_id,
+        days,
+        Utc::now().date_naive(),
+        storage.as_ref(),
+    )
+    .await?;
+
+    if as_text {
+        let xp: Vec<u32> = history.iter().map(|d| d.xp).collect();
+        let messages: Vec<u32> = history.iter().map(|d| d.messages).collect();
+        msg.channel_id
+            .send_message(&ctx.http, |m| {
+                m.embed(|e| {
+                    e.title(&title);
+                    e.field(
+                        format!("XP ({} total)", xp.iter().sum::<u32>()),
+                        sparkline(&xp),
+                        false,
+                    );
+                    e.field(
+                        format!("Messages ({} total)", messages.iter().sum::<u32>()),
+                        sparkline(&messages),
+                        false,
+                    );
+                    if let (Some(first), Some(last)) = (history.first(), history.last()) {
+                        e.footer(|f| f.text(format!("{} to {}", first.date, last.date)));
+                    }
+                    e
+                });
+                m
+            })
+            .await?;
+
+        return Ok(());
+    }
+
+    let png = tokio::task::spawn_blocking(move || render_activity_chart(&title, &history)).await??;
+    msg.channel_id
+        .send_files(
+            &ctx.http,
+            vec![AttachmentType::Bytes {
+                data: png.into(),
+                filename: String::from("activity.png"),
+            }],
+            |m| m,
+        )
+        .await?;
+
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 pub struct LeaderboardData {
-    pub member: Member,
+    pub user_id: UserId,
+    pub name: String,
     pub xp: u32,
     pub level: u32,
     pub msg_count: u32,
 }
 
-impl PartialOrd for LeaderboardData {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+/// Looks up the members on one page of a leaderboard
+///
+/// Only members on the page are looked up on Discord. `entries` are `(user_id, xp)` pairs, where
+/// the XP may be for a single period rather than the lifetime total.
+pub async fn get_ranked_leaderboard(
+    guild_id: GuildId,
+    entries: Vec<(u64, u32)>,
+    storage: &dyn Storage,
+    ctx: &Context,
+) -> StorageResult<Vec<LeaderboardData>> {
+    let mut names = HashMap::new();
+    for (user_id, _) in &entries {
+        if let Ok(member) = guild_id.member(ctx, *user_id).await {
+            names.insert(*user_id, member.display_name().to_string());
+        }
     }
+
+    rank_members(guild_id, entries, &names, storage).await
 }
-impl Ord for LeaderboardData {
-    fn cmp(&self, other: &Self) -> Ordering {
-        other.xp.cmp(&self.xp)
-    }
+
+/// Fills in leaderboard entries with level data, read all at once, and the names in `names`
+///
+/// Members missing from `names` are shown as unknown.
+pub async fn rank_members(
+    guild_id: GuildId,
+    entries: Vec<(u64, u32)>,
+    names: &HashMap<u64, String>,
+    storage: &dyn Storage,
+) -> StorageResult<Vec<LeaderboardData>> {
+    let user_ids: Vec<u64> = entries.iter().map(|(user_id, _)| *user_id).collect();
+    let levels = storage.get_user_levels(guild_id.0, &user_ids).await?;
+
+    Ok(entries
+        .into_iter()
+        .zip(levels)
+        .map(|((user_id, xp), level_data)| LeaderboardData {
+            user_id: UserId(user_id),
+            name: names
+                .get(&user_id)
+                .cloned()
+                .unwrap_or_else(|| format!("Unknown member ({})", user_id)),
+            xp,
+            level: level_data.level,
+            msg_count: level_data.msg_count,
+        })
+        .collect())
 }
 
-impl Eq for LeaderboardData {}
-impl PartialEq for LeaderboardData {
-    fn eq(&self, other: &Self) -> bool {
-        self.xp == other.xp
-    }
+const PAGE_SIZE: usize = 10;
+
+/// How long the leaderboard buttons keep working after the last press
+const PAGE_BUTTON_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Works out which zero-based page to show for a one-based page number
+///
+/// Zero counts as the first page and anything past the end as the last.
+pub fn clamp_page(requested: usize, total: usize) -> usize {
+    let page_count = total.div_ceil(PAGE_SIZE).max(1);
+    requested.saturating_sub(1).min(page_count - 1)
 }
 
-async fn get_ranked_leaderboard(
-    guild: &Guild,
-    redis_conn: &mut redis::Connection,
-    ctx: &Context,
-) -> Vec<LeaderboardData> {
-    let mut leaderboard = Vec::new();
-    let mut members_iter = guild.id.members_iter(ctx).boxed();
-    while let Some(member_res) = members_iter.next().await {
-        match member_res {
-            Ok(member) => {
-                let level_data = match get_user_level(member.user.id.0, redis_conn) {
-                    Ok(data) => data,
-                    Err(e) => {
-                        error!("Error fetching level data: {:?}", e);
-                        continue;
-                    }
-                };
-                leaderboard.push(LeaderboardData {
-                    member: member.clone(),
-                    xp: level_data.xp,
-                    level: level_data.level,
-                    msg_count: level_data.msg_count,
-                });
+/// The last zero-based page of a leaderboard
+fn last_page(total: usize) -> usize {
+    clamp_page(usize::MAX, total)
+}
+
+/// A leaderboard being shown, either all-time or for one period
+struct Board {
+    title: String,
+    /// The period ID, or `None` for the all-time board
+    period: Option<String>,
+}
+
+impl Board {
+    async fn size(&self, guild_id: GuildId, storage: &dyn Storage) -> StorageResult<usize> {
+        match &self.period {
+            Some(period) => storage.get_period_leaderboard_size(guild_id.0, period).await,
+            None => storage.get_leaderboard_size(guild_id.0).await,
+        }
+    }
+
+    async fn page(
+        &self,
+        guild_id: GuildId,
+        page_num: usize,
+        storage: &dyn Storage,
+        ctx: &Context,
+    ) -> StorageResult<Vec<LeaderboardData>> {
+        let start = PAGE_SIZE * page_num;
+        let entries = match &self.period {
+            Some(period) => {
+                storage
+                    .get_period_leaderboard_page(guild_id.0, period, start, PAGE_SIZE)
+                    .await?
             }
-            Err(e) => {
-                error!("Error fetching member: {:?}", e);
-                continue;
+            None => {
+                storage
+                    .get_leaderboard_page(guild_id.0, start, PAGE_SIZE)
+                    .await?
             }
-        }
+        };
+
+        get_ranked_leaderboard(guild_id, entries, storage, ctx).await
     }
+}
 
-    leaderboard.sort();
+fn leaderboard_embed(
+    e: &mut CreateEmbed,
+    title: &str,
+    page_num: usize,
+    total: usize,
+    page: &[LeaderboardData],
+) {
+    e.title(title);
+    e.description(format!(
+        "**Page {} of {}:** {}-{} of {}",
+        page_num + 1,
+        last_page(total) + 1,
+        PAGE_SIZE * page_num + 1,
+        PAGE_SIZE * page_num + page.len(),
+        total
+    ));
 
-    leaderboard
+    for (i, l) in page.iter().enumerate() {
+        e.field(
+            format!("#{}: {}", PAGE_SIZE * page_num + i + 1, l.name),
+            format!("{} Exp.\tLvl. {}\t{} Messages", l.xp, l.level, l.msg_count),
+            false,
+        );
+    }
+}
+
+/// Adds the first, previous, next and last page buttons
+fn leaderboard_buttons(c: &mut CreateComponents, page_num: usize, total: usize) {
+    let last = last_page(total);
+    c.create_action_row(|r| {
+        for (id, label, disabled) in &[
+            ("first", "⏮", page_num == 0),
+            ("previous", "◀", page_num == 0),
+            ("next", "▶", page_num >= last),
+            ("last", "⏭", page_num >= last),
+        ] {
+            r.create_button(|b| {
+                b.custom_id(id)
+                    .label(label)
+                    .style(ButtonStyle::Secondary)
+                    .disabled(*disabled)
+            });
+        }
+        r
+    });
 }
 
 #[command]
-#[description = "Checks the server leaderboard"]
-#[max_args(1)]
-#[usage("[page]")]
+#[description = "Checks the server leaderboard, for all time or just this week, month or season"]
+#[only_in(guilds)]
+#[max_args(3)]
+#[usage("[weekly | monthly | season [name]] [page]")]
 pub async fn levels(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let mut bot_data = ctx.data.write().await;
-    let redis_conn = bot_data.get_mut::<RedisConnection>().unwrap();
-    let mut leaderboard =
-        get_ranked_leaderboard(&msg.guild(&ctx).await.unwrap(), redis_conn, ctx).await;
-    leaderboard.sort();
+    let guild_id = msg.guild_id.unwrap();
+    let storage = storage(ctx).await;
 
-    let PAGE_SIZE = 10;
-
-    let mut iter = leaderboard.chunks(PAGE_SIZE);
-
-    let page_num = match args.parse::<usize>() {
-        Ok(num) => num - 1,
-        Err(_) => 0,
+    let mut raw = args.raw().peekable();
+    let period = raw.peek().and_then(|arg| Period::parse(arg));
+    if period.is_some() {
+        raw.next();
+    }
+    // A season can be picked by name to look back at an old one
+    let season_name = match raw.peek() {
+        Some(arg) if period == Some(Period::Season) && arg.parse::<usize>().is_err() => Some(*arg),
+        _ => None,
+    };
+    if season_name.is_some() {
+        raw.next();
+    }
+    let requested_page = match raw.next().map(|arg| arg.parse::<usize>()) {
+        Some(Ok(num)) => num,
+        _ => 1,
     };
 
-    let page = match iter.nth(page_num) {
-        Some(p) => p.to_vec(),
-        None => iter.nth(1).unwrap().to_vec(),
+    let board = match period {
+        Some(Period::Season) => {
+            let season = match season_name {
+                Some(name) => Some(name.to_string()),
+                None => get_current_season(guild_id.0, storage.as_ref()).await?,
+            };
+            match season {
+                Some(season) => Board {
+                    title: format!("Leaderboard: Season {}", season),
+                    period: Some(season_id(&season)),
+                },
+                None => {
+                    msg.channel_id
+                        .say(&ctx.http, "There's no season running")
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        Some(period) => Board {
+            title: format!("Leaderboard: {}", period.title()),
+            period: period_id(period, Utc::now(), None),
+        },
+        None => Board {
+            title: String::from("Leaderboard"),
+            period: None,
+        },
     };
 
-    msg.channel_id
+    let mut total = board.size(guild_id, storage.as_ref()).await?;
+    if total == 0 {
+        msg.channel_id
+            .send_message(&ctx.http, |m| {
+                m.embed(|e| {
+                    e.title(&board.title);
+                    e.description("Nobody has earned any XP yet");
+                    e
+                })
+            })
+            .await?;
+        return Ok(());
+    }
+    let mut page_num = clamp_page(requested_page, total);
+    let page = board
+        .page(guild_id, page_num, storage.as_ref(), ctx)
+        .await?;
+
+    let mut leaderboard_msg = msg
+        .channel_id
         .send_message(&ctx.http, |m| {
             m.embed(|e| {
-                e.title("Leaderboard");
-                e.description(format!(
-                    "**Page {}:** {}-{} of {}",
-                    (page_num + 1).to_string(),
-                    PAGE_SIZE * page_num + 1,
-                    PAGE_SIZE * (page_num + 1),
-                    leaderboard.len()
-                ));
-
-                for (i, l) in page.iter().enumerate() {
-                    e.field(
-                        format!("#{}: {}", PAGE_SIZE * page_num + i + 1, l.member.display_name()),
-                        format!("{} Exp.\tLvl. {}\t{} Messages", l.xp, l.level, l.msg_count),
-                        false,
-                    );
-                }
+                leaderboard_embed(e, &board.title, page_num, total, &page);
                 e
             });
+            m.components(|c| {
+                leaderboard_buttons(c, page_num, total);
+                c
+            });
             m
         })
         .await?;
 
-    Ok(())
+    let paging = async {
+        // Only whoever asked for the leaderboard can page through it
+        while let Some(interaction) = leaderboard_msg
+            .await_component_interaction(ctx)
+            .author_id(msg.author.id)
+            .timeout(PAGE_BUTTON_TIMEOUT)
+            .await
+        {
+            // The leaderboard may have grown or shrunk since the last page was shown
+            total = board.size(guild_id, storage.as_ref()).await?;
+            page_num = match interaction.data.custom_id.as_str() {
+                "first" => 0,
+                "previous" => page_num.saturating_sub(1),
+                "next" => page_num + 1,
+                _ => last_page(total),
+            }
+            .min(last_page(total));
+            let page = board
+                .page(guild_id, page_num, storage.as_ref(), ctx)
+                .await?;
+
+            interaction
+                .create_interaction_response(&ctx.http, |r| {
+                    r.kind(InteractionResponseType::UpdateMessage)
+                        .interaction_response_data(|d| {
+                            d.create_embed(|e| {
+                                leaderboard_embed(e, &board.title, page_num, total, &page);
+                                e
+                            });
+                            d.components(|c| {
+                                leaderboard_buttons(c, page_num, total);
+                                c
+                            })
+                        })
+                })
+                .await?;
+        }
+
+        Ok::<(), CommandError>(())
+    }
+    .await;
+
+    // Take the buttons away once they stop working, even if paging failed
+    leaderboard_msg
+        .edit(&ctx.http, |m| m.components(|c| c))
+        .await?;
+
+    paging
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn page_zero_is_first_page() {
+        assert_eq!(clamp_page(0, 35), 0);
+        assert_eq!(clamp_page(1, 35), 0);
+    }
+
+    #[test]
+    fn pages_past_the_end_show_last_page() {
+        assert_eq!(clamp_page(4, 35), 3);
+        assert_eq!(clamp_page(99, 35), 3);
+        assert_eq!(clamp_page(99, 40), 3);
+    }
+
+    #[test]
+    fn empty_leaderboard_has_one_page() {
+        assert_eq!(clamp_page(5, 0), 0);
+        assert_eq!(last_page(0), 0);
+    }
 }
@@ -1,7 +1,30 @@
+use crate::commands::leveling::{get_ranked_leaderboard, rank_members};
+use crate::util::abuse::{get_abuse_rules, parse_rule, set_abuse_rule, RULE_NAMES};
+use crate::util::audit::{XpAuditEntry, XpChange};
+use crate::util::config::LevelUpTarget;
+use crate::util::data::{leveling_config, storage};
+use crate::util::escalation::{
+    get_escalation_emoji, parse_emoji, set_escalation_emoji, ESCALATION_ANONYMOUS_SETTING,
+    ESCALATION_CHANNEL_SETTING, ESCALATION_EMOJI_SETTING,
+};
+use crate::util::export::{export_rows, ExportFormat, ExportRow};
+use crate::util::import::{apply_record, parse_csv, parse_json, ImportChange, ImportMode};
+use crate::util::leveling::get_level_number;
+use crate::util::multipliers::XpTarget;
+use crate::util::periods::{
+    current_period_ids, get_current_season, is_valid_season_name, season_id, SEASON_SETTING,
+};
+use crate::util::reports::ReportAuditEntry;
+use crate::util::rewards::{get_keep_lower_rewards, sync_member_rewards};
+
 use serenity::framework::standard::{macros::command, Args, CommandResult};
+use serenity::http::AttachmentType;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 use serenity::utils::ArgumentConvert;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::error;
 
 #[command]
 #[description = "Removes the specified number of messages from a channel"]
@@ -103,3 +126,1284 @@ pub async fn reactmsg(ctx: &Context, msg: &Message, mut args: Args) -> CommandRe
 
     Ok(())
 }
+
+#[command]
+#[description = "Moves XP stored before per-server leveling into the given server"]
+#[num_args(1)]
+#[owners_only]
+#[usage("<server id>")]
+pub async fn migratelevels(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id: u64 = args.parse::<u64>()?;
+
+    let storage = storage(ctx).await;
+    let migrated = storage.migrate_global_levels(guild_id).await?;
+
+    msg.channel_id
+        .say(&ctx, format!("Migrated {} members into {}", migrated, guild_id))
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Sets where level-up announcements are posted"]
+#[only_in(guilds)]
+#[num_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<same | dm | off | #channel>")]
+pub async fn levelup(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let target = match LevelUpTarget::parse(args.rest()) {
+        Some(target) => target,
+        None => {
+            msg.channel_id
+                .say(&ctx, "Expected `same`, `dm`, `off` or a channel")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let storage = storage(ctx).await;
+    storage
+        .set_setting(msg.guild_id.unwrap().0, "levelup_target", &target.to_setting())
+        .await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Sets the level-up announcement. `{user}`, `{old}` and `{new}` are filled in with the member's mention and their old and new levels"]
+#[only_in(guilds)]
+#[min_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<message>")]
+pub async fn levelupmsg(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let storage = storage(ctx).await;
+    storage
+        .set_setting(msg.guild_id.unwrap().0, "levelup_template", args.rest())
+        .await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Grants a role to members once they reach a level"]
+#[only_in(guilds)]
+#[min_args(2)]
+#[required_permissions("MANAGE_ROLES")]
+#[usage("<level> <role>")]
+pub async fn reward(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let level = args.single::<u32>()?;
+    let role = Role::convert(ctx, msg.guild_id, Some(msg.channel_id), args.rest()).await?;
+
+    let storage = storage(ctx).await;
+    storage
+        .set_reward(msg.guild_id.unwrap().0, level, role.id)
+        .await?;
+
+    msg.channel_id
+        .say(&ctx, format!("Members will get {} at level {}", role.name, level))
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Stops granting a role for reaching a level"]
+#[only_in(guilds)]
+#[num_args(1)]
+#[required_permissions("MANAGE_ROLES")]
+#[usage("<level>")]
+pub async fn unreward(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let level = args.parse::<u32>()?;
+
+    let storage = storage(ctx).await;
+    storage.remove_reward(msg.guild_id.unwrap().0, level).await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Lists the roles granted for reaching each level"]
+#[only_in(guilds)]
+#[num_args(0)]
+#[required_permissions("MANAGE_ROLES")]
+pub async fn rewards(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap().0;
+    let storage = storage(ctx).await;
+    let rewards = storage.get_rewards(guild_id).await?;
+    let keep_lower = get_keep_lower_rewards(guild_id, storage.as_ref()).await?;
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.title("Level rewards");
+                if keep_lower {
+                    e.description("Lower rewards are kept");
+                } else {
+                    e.description("Lower rewards are replaced");
+                }
+                for (level, role) in rewards {
+                    e.field(format!("Level {}", level), format!("<@&{}>", role.0), true);
+                }
+                e
+            });
+            m
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Sets whether lower reward roles are kept or replaced when a member reaches a higher one"]
+#[only_in(guilds)]
+#[num_args(1)]
+#[required_permissions("MANAGE_ROLES")]
+#[usage("<keep | replace>")]
+pub async fn rewardmode(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let mode = args.rest();
+    if mode != "keep" && mode != "replace" {
+        msg.channel_id
+            .say(&ctx, "Expected `keep` or `replace`")
+            .await?;
+        return Ok(());
+    }
+
+    let storage = storage(ctx).await;
+    storage
+        .set_setting(msg.guild_id.unwrap().0, "reward_mode", mode)
+        .await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Fixes up everyone's reward roles to match their stored XP, including members who hold a reward role without any XP"]
+#[only_in(guilds)]
+#[num_args(0)]
+#[required_permissions("MANAGE_ROLES")]
+pub async fn resyncroles(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let storage = storage(ctx).await;
+    let config = leveling_config(ctx).await;
+
+    let total = storage.get_leaderboard_size(guild_id.0).await?;
+    let mut levels: HashMap<u64, u32> = storage
+        .get_leaderboard_page(guild_id.0, 0, total)
+        .await?
+        .into_iter()
+        .map(|(user_id, xp)| (user_id, get_level_number(&config.curve, xp)))
+        .collect();
+    // Members off the leaderboard have no XP, but can still be holding reward roles
+    let reward_roles: Vec<RoleId> = storage
+        .get_rewards(guild_id.0)
+        .await?
+        .into_iter()
+        .map(|(_, role)| role)
+        .collect();
+    let holders = ctx
+        .cache
+        .guild_field(guild_id, |guild| {
+            guild
+                .members
+                .values()
+                .filter(|member| member.roles.iter().any(|role| reward_roles.contains(role)))
+                .map(|member| member.user.id.0)
+                .collect::<Vec<u64>>()
+        })
+        .await
+        .unwrap_or_default();
+    for user_id in holders {
+        levels.entry(user_id).or_insert(0);
+    }
+
+    let mut failed = 0;
+    for (user_id, level) in &levels {
+        if let Err(e) = sync_member_rewards(ctx, guild_id, *user_id, *level, storage.as_ref()).await
+        {
+            // Most likely the member has left the server
+            error!("Could not sync rewards for {}: {:?}", user_id, e);
+            failed += 1;
+        }
+    }
+
+    msg.channel_id
+        .say(
+            &ctx,
+            format!(
+                "Synced roles for {} members ({} skipped)",
+                levels.len() - failed,
+                failed
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Applies a staff change to a member's totals, logs it and fixes up their reward roles
+///
+/// `args` should hold the member, then the amount if `change` needs one, then an optional
+/// reason.
+async fn adjust_member_xp(
+    ctx: &Context,
+    msg: &Message,
+    mut args: Args,
+    change: fn(u32) -> XpChange,
+    takes_amount: bool,
+) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let member = match Member::convert(
+        ctx,
+        msg.guild_id,
+        Some(msg.channel_id),
+        &args.single_quoted::<String>()?,
+    )
+    .await
+    {
+        Ok(member) => member,
+        Err(_) => {
+            msg.channel_id
+                .say(&ctx, "Could not find that member")
+                .await?;
+            return Ok(());
+        }
+    };
+    let change = if takes_amount {
+        change(args.single::<u32>()?)
+    } else {
+        change(0)
+    };
+    let reason = match args.rest() {
+        "" => String::from("No reason given"),
+        reason => reason.to_string(),
+    };
+
+    let storage = storage(ctx).await;
+    let periods = current_period_ids(guild_id.0, msg.timestamp, storage.as_ref()).await?;
+    let (old_data, new_data) = storage
+        .adjust_user_level(guild_id.0, member.user.id.0, change, &periods)
+        .await?;
+
+    let entry = XpAuditEntry {
+        staff_id: msg.author.id.0,
+        user_id: Some(member.user.id.0),
+        action: change.name().to_string(),
+        xp_before: old_data.xp,
+        xp_after: new_data.xp,
+        msg_count_before: old_data.msg_count,
+        msg_count_after: new_data.msg_count,
+        reason,
+        time: msg.timestamp,
+    };
+    storage.add_xp_audit_entry(guild_id.0, &entry).await?;
+
+    sync_member_rewards(
+        ctx,
+        guild_id,
+        member.user.id.0,
+        new_data.level,
+        storage.as_ref(),
+    )
+    .await?;
+
+    msg.channel_id
+        .say(
+            &ctx,
+            format!(
+                "{} now has {} XP (level {}) and {} messages",
+                member.display_name(),
+                new_data.xp,
+                new_data.level,
+                new_data.msg_count
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Gives a member XP"]
+#[only_in(guilds)]
+#[min_args(2)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<member> <xp> [reason]")]
+pub async fn givexp(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    adjust_member_xp(ctx, msg, args, XpChange::Give, true).await
+}
+
+#[command]
+#[description = "Takes XP away from a member"]
+#[only_in(guilds)]
+#[min_args(2)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<member> <xp> [reason]")]
+pub async fn takexp(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    adjust_member_xp(ctx, msg, args, XpChange::Take, true).await
+}
+
+#[command]
+#[description = "Sets a member's XP"]
+#[only_in(guilds)]
+#[min_args(2)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<member> <xp> [reason]")]
+pub async fn setxp(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    adjust_member_xp(ctx, msg, args, XpChange::Set, true).await
+}
+
+#[command]
+#[description = "Sets a member's message count"]
+#[only_in(guilds)]
+#[min_args(2)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<member> <messages> [reason]")]
+pub async fn setmessages(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    adjust_member_xp(ctx, msg, args, XpChange::SetMessages, true).await
+}
+
+#[command]
+#[description = "Resets a member's XP and message count"]
+#[only_in(guilds)]
+#[min_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<member> [reason]")]
+pub async fn resetxp(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    adjust_member_xp(ctx, msg, args, |_| XpChange::Reset, false).await
+}
+
+#[command]
+#[description = "Resets everyone's XP, message count and activity history in the server. Asks for confirmation first"]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("[reason]")]
+pub async fn resetguildxp(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let storage = storage(ctx).await;
+    let total = storage.get_leaderboard_size(guild_id.0).await?;
+
+    msg.channel_id
+        .say(
+            &ctx,
+            format!(
+                "This will wipe XP, messages and activity for all {} members. Reply `confirm` within 30 seconds to go ahead",
+                total
+            ),
+        )
+        .await?;
+
+    let confirmed = match msg
+        .author
+        .await_reply(ctx)
+        .channel_id(msg.channel_id)
+        .timeout(Duration::from_secs(30))
+        .await
+    {
+        Some(reply) => reply.content.trim().eq_ignore_ascii_case("confirm"),
+        None => false,
+    };
+    if !confirmed {
+        msg.channel_id.say(&ctx, "Reset cancelled").await?;
+        return Ok(());
+    }
+
+    // Finished weeks, months and seasons are kept
+    let periods = current_period_ids(guild_id.0, msg.timestamp, storage.as_ref()).await?;
+    let reset = storage.reset_guild_levels(guild_id.0, &periods).await?;
+    let entry = XpAuditEntry {
+        staff_id: msg.author.id.0,
+        user_id: None,
+        action: XpChange::Reset.name().to_string(),
+        xp_before: 0,
+        xp_after: 0,
+        msg_count_before: 0,
+        msg_count_after: 0,
+        reason: match args.rest() {
+            "" => format!("Reset {} members", reset),
+            reason => format!("{} (reset {} members)", reason, reset),
+        },
+        time: msg.timestamp,
+    };
+    storage.add_xp_audit_entry(guild_id.0, &entry).await?;
+
+    msg.channel_id
+        .say(
+            &ctx,
+            format!(
+                "Reset {} members. Run `resyncroles` if reward roles should be taken away too",
+                reset
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Lists recent staff changes to XP, optionally for one member"]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("[member]")]
+pub async fn xplog(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let user_id = if args.is_empty() {
+        None
+    } else {
+        match Member::convert(ctx, msg.guild_id, Some(msg.channel_id), args.rest()).await {
+            Ok(member) => Some(member.user.id.0),
+            Err(_) => {
+                msg.channel_id
+                    .say(&ctx, "Could not find that member")
+                    .await?;
+                return Ok(());
+            }
+        }
+    };
+
+    let storage = storage(ctx).await;
+    let entries: Vec<XpAuditEntry> = storage
+        .get_xp_audit_entries(guild_id.0, 100)
+        .await?
+        .into_iter()
+        .filter(|entry| user_id.is_none() || entry.user_id == user_id)
+        .take(10)
+        .collect();
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.title("XP changes");
+                if entries.is_empty() {
+                    e.description("Nothing yet");
+                }
+                for entry in &entries {
+                    e.field(entry.time.format("%Y-%m-%d %H:%M UTC"), entry.describe(), false);
+                }
+                e
+            });
+            m
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Looks up a channel, category or role by mention, ID or name
+async fn parse_xp_target(ctx: &Context, msg: &Message, arg: &str) -> Option<XpTarget> {
+    if let Ok(channel) = Channel::convert(ctx, msg.guild_id, Some(msg.channel_id), arg).await {
+        return Some(XpTarget::Channel(channel.id()));
+    }
+    if let Ok(role) = Role::convert(ctx, msg.guild_id, Some(msg.channel_id), arg).await {
+        return Some(XpTarget::Role(role.id));
+    }
+
+    None
+}
+
+#[command]
+#[description = "Multiplies the XP earned in a channel or category, or by members with a role. A multiplier of 0 stops XP entirely"]
+#[only_in(guilds)]
+#[min_args(2)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<multiplier> <channel | category | role>")]
+pub async fn xpmultiplier(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let multiplier = args.single::<f64>()?;
+    if !multiplier.is_finite() || multiplier < 0.0 {
+        msg.channel_id
+            .say(&ctx, "The multiplier can't be negative")
+            .await?;
+        return Ok(());
+    }
+    let target = match parse_xp_target(ctx, msg, args.rest()).await {
+        Some(target) => target,
+        None => {
+            msg.channel_id
+                .say(&ctx, "Could not find that channel or role")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let storage = storage(ctx).await;
+    storage
+        .set_xp_multiplier(msg.guild_id.unwrap().0, target, multiplier)
+        .await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Stops a channel, category or role earning XP"]
+#[only_in(guilds)]
+#[min_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<channel | category | role>")]
+pub async fn noxp(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let target = match parse_xp_target(ctx, msg, args.rest()).await {
+        Some(target) => target,
+        None => {
+            msg.channel_id
+                .say(&ctx, "Could not find that channel or role")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let storage = storage(ctx).await;
+    storage
+        .set_xp_multiplier(msg.guild_id.unwrap().0, target, 0.0)
+        .await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Puts a channel, category or role back to normal XP"]
+#[only_in(guilds)]
+#[min_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<channel | category | role>")]
+pub async fn clearxpmultiplier(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let target = match parse_xp_target(ctx, msg, args.rest()).await {
+        Some(target) => target,
+        None => {
+            msg.channel_id
+                .say(&ctx, "Could not find that channel or role")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let storage = storage(ctx).await;
+    storage
+        .remove_xp_multiplier(msg.guild_id.unwrap().0, target)
+        .await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Lists the server's XP multipliers and no-XP channels and roles"]
+#[only_in(guilds)]
+#[num_args(0)]
+#[required_permissions("MANAGE_GUILD")]
+pub async fn xpmultipliers(ctx: &Context, msg: &Message) -> CommandResult {
+    let storage = storage(ctx).await;
+    let mut multipliers = storage.get_xp_multipliers(msg.guild_id.unwrap().0).await?;
+    multipliers.sort_by_key(|(target, _)| target.key());
+
+    let lines: Vec<String> = multipliers
+        .iter()
+        .map(|(target, multiplier)| {
+            if *multiplier == 0.0 {
+                format!("{}: no XP", target.mention())
+            } else {
+                format!("{}: {}x", target.mention(), multiplier)
+            }
+        })
+        .collect();
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.title("XP multipliers");
+                if lines.is_empty() {
+                    e.description("Everything earns normal XP");
+                } else {
+                    e.description(lines.join("\n"));
+                }
+                e
+            });
+            m
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Starts tallying XP for a new season, such as a semester"]
+#[only_in(guilds)]
+#[num_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<name>")]
+pub async fn startseason(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap().0;
+    let name = args.rest();
+    let storage = storage(ctx).await;
+
+    if !is_valid_season_name(name) {
+        msg.channel_id
+            .say(
+                &ctx,
+                "Season names need a letter in them, since `levels season` reads a number as a page",
+            )
+            .await?;
+        return Ok(());
+    }
+    if let Some(current) = get_current_season(guild_id, storage.as_ref()).await? {
+        msg.channel_id
+            .say(
+                &ctx,
+                format!("Season {} is still running. End it with `endseason` first", current),
+            )
+            .await?;
+        return Ok(());
+    }
+    // Seasons are looked up by name later, so reusing one would mix two seasons together
+    if storage
+        .get_period_leaderboard_size(guild_id, &season_id(name))
+        .await?
+        > 0
+    {
+        msg.channel_id
+            .say(&ctx, format!("There's already been a season called {}", name))
+            .await?;
+        return Ok(());
+    }
+
+    storage.set_setting(guild_id, SEASON_SETTING, name).await?;
+
+    msg.channel_id
+        .say(&ctx, format!("Season {} has started!", name))
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Ends the current season, freezing its leaderboard and posting the top members"]
+#[only_in(guilds)]
+#[max_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("[number of members to post]")]
+pub async fn endseason(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let count = if args.is_empty() {
+        10
+    } else {
+        // Embeds can't hold more than 25 fields
+        args.parse::<usize>()?.min(25)
+    };
+    let storage = storage(ctx).await;
+
+    let season = match get_current_season(guild_id.0, storage.as_ref()).await? {
+        Some(season) => season,
+        None => {
+            msg.channel_id.say(&ctx, "There's no season running").await?;
+            return Ok(());
+        }
+    };
+
+    // Nothing is added to the season's board once it isn't the current season, so clearing the
+    // setting is what archives it
+    storage.clear_setting(guild_id.0, SEASON_SETTING).await?;
+
+    let id = season_id(&season);
+    let total = storage.get_period_leaderboard_size(guild_id.0, &id).await?;
+    let entries = storage
+        .get_period_leaderboard_page(guild_id.0, &id, 0, count)
+        .await?;
+    let standings = get_ranked_leaderboard(guild_id, entries, storage.as_ref(), ctx).await?;
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.title(format!("Season {} final standings", season));
+                e.description(format!(
+                    "{} members took part. See the full standings with `levels season {}`",
+                    total, season
+                ));
+                for (i, l) in standings.iter().enumerate() {
+                    e.field(
+                        format!("#{}: {}", i + 1, l.name),
+                        format!("{} Exp.", l.xp),
+                        false,
+                    );
+                }
+                e
+            });
+            m
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Imports levels from another bot's JSON or CSV export, attached to the message. Shows what would change unless `--apply` is given. `--keep-levels` keeps everyone's old level instead of working it out from their XP. XP earned while an import runs is overwritten, so run it while the server is quiet"]
+#[only_in(guilds)]
+#[owners_only]
+#[max_args(2)]
+#[usage("[--keep-levels] [--apply]")]
+pub async fn importlevels(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let mut apply = false;
+    let mut mode = ImportMode::RecomputeLevels;
+    for arg in args.raw() {
+        match arg {
+            "--apply" => apply = true,
+            "--keep-levels" => mode = ImportMode::KeepLevels,
+            _ => {
+                msg.channel_id
+                    .say(&ctx, format!("Unknown option `{}`", arg))
+                    .await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let attachment = match msg.attachments.first() {
+        Some(attachment) => attachment,
+        None => {
+            msg.channel_id
+                .say(&ctx, "Attach a JSON or CSV export to import")
+                .await?;
+            return Ok(());
+        }
+    };
+    let data = String::from_utf8(attachment.download().await?)?;
+    let parsed = if attachment.filename.to_lowercase().ends_with(".csv") {
+        parse_csv(&data)
+    } else {
+        parse_json(&data)
+    };
+    let (records, skipped) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            msg.channel_id
+                .say(&ctx, format!("Could not read the export: {}", e))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let storage = storage(ctx).await;
+    let config = leveling_config(ctx).await;
+    let user_ids: Vec<u64> = records.iter().map(|record| record.user_id).collect();
+    let levels = storage.get_user_levels(guild_id.0, &user_ids).await?;
+    let mut changes: Vec<ImportChange> = records
+        .iter()
+        .zip(levels)
+        .map(|(record, before)| ImportChange {
+            user_id: record.user_id,
+            after: apply_record(record, &before, mode, &config.curve),
+            before,
+        })
+        .collect();
+    let existing = changes
+        .iter()
+        .filter(|change| change.before.xp > 0 || change.before.msg_count > 0)
+        .count();
+
+    if apply {
+        for change in &changes {
+            storage
+                .set_user_level(guild_id.0, change.user_id, change.after.clone())
+                .await?;
+        }
+        let entry = XpAuditEntry {
+            staff_id: msg.author.id.0,
+            user_id: None,
+            action: String::from("import"),
+            xp_before: 0,
+            xp_after: 0,
+            msg_count_before: 0,
+            msg_count_after: 0,
+            reason: format!(
+                "Imported {} members from {}",
+                changes.len(),
+                attachment.filename
+            ),
+            time: msg.timestamp,
+        };
+        storage.add_xp_audit_entry(guild_id.0, &entry).await?;
+    }
+
+    changes.sort_by_key(|change| std::cmp::Reverse(change.after.xp));
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                if apply {
+                    e.title("Import finished");
+                } else {
+                    e.title("Import dry run");
+                }
+                e.description(format!(
+                    "{} members read, {} already have XP here and would be overwritten, {} entries skipped.{}",
+                    changes.len(),
+                    existing,
+                    skipped.len(),
+                    if apply {
+                        " Run `resyncroles` to hand out reward roles"
+                    } else {
+                        " Run again with `--apply` to write it"
+                    }
+                ));
+                for change in changes.iter().take(10) {
+                    // Mentions don't render in field names
+                    e.field(
+                        change.user_id.to_string(),
+                        format!(
+                            "<@{}>: level {} → {}, {} → {} XP, {} → {} messages",
+                            change.user_id,
+                            change.before.level,
+                            change.after.level,
+                            change.before.xp,
+                            change.after.xp,
+                            change.before.msg_count,
+                            change.after.msg_count
+                        ),
+                        false,
+                    );
+                }
+                if !skipped.is_empty() {
+                    let mut shown: Vec<&str> =
+                        skipped.iter().take(10).map(|s| s.as_str()).collect();
+                    if skipped.len() > shown.len() {
+                        shown.push("...");
+                    }
+                    e.field("Skipped", shown.join("\n"), false);
+                }
+                e
+            });
+            m
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Gets the display name of everyone in a guild, a thousand members per request
+async fn get_member_names(
+    ctx: &Context,
+    guild_id: GuildId,
+) -> serenity::Result<HashMap<u64, String>> {
+    let mut names = HashMap::new();
+    let mut after = None;
+    loop {
+        let members = guild_id.members(&ctx.http, Some(1000), after).await?;
+        for member in &members {
+            names.insert(member.user.id.0, member.display_name().to_string());
+        }
+        match members.last() {
+            Some(last) if members.len() == 1000 => after = Some(last.user.id),
+            _ => break,
+        }
+    }
+
+    Ok(names)
+}
+
+#[command]
+#[description = "Exports the whole leaderboard as a CSV or JSON file"]
+#[only_in(guilds)]
+#[max_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("[csv | json]")]
+pub async fn exportlevels(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let format = if args.is_empty() {
+        ExportFormat::Csv
+    } else {
+        match ExportFormat::parse(args.rest()) {
+            Some(format) => format,
+            None => {
+                msg.channel_id
+                    .say(&ctx, "Expected `csv` or `json`")
+                    .await?;
+                return Ok(());
+            }
+        }
+    };
+
+    // Looking everyone up can take a while on big servers
+    msg.channel_id.broadcast_typing(&ctx).await?;
+
+    let storage = storage(ctx).await;
+    let total = storage.get_leaderboard_size(guild_id.0).await?;
+    let entries = storage.get_leaderboard_page(guild_id.0, 0, total).await?;
+    let names = get_member_names(ctx, guild_id).await?;
+    let leaderboard = rank_members(guild_id, entries, &names, storage.as_ref()).await?;
+    let rows: Vec<ExportRow> = leaderboard
+        .into_iter()
+        .enumerate()
+        .map(|(i, l)| ExportRow {
+            rank: i + 1,
+            user_id: l.user_id.0.to_string(),
+            name: l.name,
+            xp: l.xp,
+            level: l.level,
+            messages: l.msg_count,
+        })
+        .collect();
+    let data = export_rows(&rows, format)?;
+
+    msg.channel_id
+        .send_files(
+            &ctx.http,
+            vec![AttachmentType::Bytes {
+                data: data.into(),
+                filename: format!(
+                    "leaderboard-{}-{}.{}",
+                    guild_id.0,
+                    msg.timestamp.format("%Y-%m-%d"),
+                    format.extension()
+                ),
+            }],
+            |m| m.content(format!("Exported {} members", rows.len())),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Shows the anti-abuse rules, or changes one. They all start off. `min_length` takes a number of characters, `rate` takes `messages/seconds` or `off`, and `repeats`, `emoji_only` and `links_only` take `on` or `off`"]
+#[only_in(guilds)]
+#[max_args(2)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("[<min_length | repeats | emoji_only | links_only | rate> <value>]")]
+pub async fn xprule(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap().0;
+    let storage = storage(ctx).await;
+
+    if !args.is_empty() {
+        let name = args.single::<String>()?;
+        let value = args.rest();
+        let value = match parse_rule(&name, value) {
+            Some(value) => value,
+            None => {
+                msg.channel_id
+                    .say(
+                        &ctx,
+                        format!(
+                            "Expected one of {} followed by a valid value",
+                            RULE_NAMES.join(", ")
+                        ),
+                    )
+                    .await?;
+                return Ok(());
+            }
+        };
+        set_abuse_rule(guild_id, &name, &value, storage.as_ref()).await?;
+    }
+
+    let rules = get_abuse_rules(guild_id, storage.as_ref()).await?;
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.title("Anti-abuse rules");
+                e.field("min_length", rules.min_length.to_string(), true);
+                e.field("repeats", on_off(rules.block_repeats), true);
+                e.field("emoji_only", on_off(rules.block_emoji_only), true);
+                e.field("links_only", on_off(rules.block_links_only), true);
+                e.field(
+                    "rate",
+                    if rules.rate_limit == 0 {
+                        String::from("off")
+                    } else {
+                        format!(
+                            "{} messages in {} seconds",
+                            rules.rate_limit,
+                            rules.rate_window.num_seconds()
+                        )
+                    },
+                    true,
+                );
+                e
+            });
+            m
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Lists a member's recent messages that were refused XP, and times they were flagged for posting too fast"]
+#[only_in(guilds)]
+#[min_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<member>")]
+pub async fn xprefusals(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap().0;
+    let member = match Member::convert(ctx, msg.guild_id, Some(msg.channel_id), args.rest()).await
+    {
+        Ok(member) => member,
+        Err(_) => {
+            msg.channel_id
+                .say(&ctx, "Could not find that member")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let storage = storage(ctx).await;
+    let entries = storage
+        .get_abuse_entries(guild_id, member.user.id.0, 20)
+        .await?;
+    let lines: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "`{}` `{}` in <#{}>",
+                entry.time.format("%Y-%m-%d %H:%M"),
+                entry.reason.code(),
+                entry.channel_id
+            )
+        })
+        .collect();
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.title(format!("XP refusals for {}", member.display_name()));
+                if lines.is_empty() {
+                    e.description("Nothing yet");
+                } else {
+                    e.description(lines.join("\n"));
+                }
+                e
+            });
+            m
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Sets the channel reported messages are forwarded to, or `off` to stop forwarding them"]
+#[only_in(guilds)]
+#[num_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<#channel | off>")]
+pub async fn reportchannel(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let storage = storage(ctx).await;
+
+    if args.rest() == "off" {
+        // Clearing it would fall back to LOGGING_CHANNEL
+        storage
+            .set_setting(guild_id.0, ESCALATION_CHANNEL_SETTING, "off")
+            .await?;
+        msg.react(&ctx, '✅').await?;
+        return Ok(());
+    }
+
+    let channel = match Channel::convert(ctx, msg.guild_id, Some(msg.channel_id), args.rest())
+        .await
+        .ok()
+        .and_then(|channel| channel.guild())
+        .filter(|channel| channel.guild_id == guild_id)
+    {
+        Some(channel) => channel,
+        None => {
+            msg.channel_id
+                .say(&ctx, "Expected `off` or a channel in this server")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    storage
+        .set_setting(
+            guild_id.0,
+            ESCALATION_CHANNEL_SETTING,
+            &channel.id.0.to_string(),
+        )
+        .await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Shows the emoji members react with to report a message, or sets them. Custom emoji from this server work too, and `reset` goes back to ❗ ‼️ ⁉️ ❕"]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("[emoji...] | reset")]
+pub async fn reportemoji(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap().0;
+    let storage = storage(ctx).await;
+
+    if args.rest() == "reset" {
+        storage
+            .clear_setting(guild_id, ESCALATION_EMOJI_SETTING)
+            .await?;
+    } else if !args.is_empty() {
+        let mut emoji = Vec::new();
+        for arg in args.raw() {
+            match parse_emoji(arg) {
+                Some(parsed) => emoji.push(parsed),
+                None => {
+                    msg.channel_id
+                        .say(&ctx, format!("{} isn't an emoji", arg))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        set_escalation_emoji(guild_id, &emoji, storage.as_ref()).await?;
+    }
+
+    let emoji: Vec<String> = get_escalation_emoji(guild_id, storage.as_ref())
+        .await?
+        .iter()
+        .map(|e| e.to_string())
+        .collect();
+    msg.channel_id
+        .say(
+            &ctx,
+            format!("Members can report messages with {}", emoji.join(" ")),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Sets whether new reports leave out who reported the message. Older reports are made anonymous too when they're next reported, but never shown again once hidden. Reporters are still recorded in `reportlog`"]
+#[only_in(guilds)]
+#[num_args(1)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("<on | off>")]
+pub async fn reportanonymous(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let mode = args.rest();
+    if mode != "on" && mode != "off" {
+        msg.channel_id.say(&ctx, "Expected `on` or `off`").await?;
+        return Ok(());
+    }
+
+    let storage = storage(ctx).await;
+    storage
+        .set_setting(msg.guild_id.unwrap().0, ESCALATION_ANONYMOUS_SETTING, mode)
+        .await?;
+
+    msg.react(&ctx, '✅').await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Lists who reported messages, including anonymous reports, optionally for one report or member"]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+#[usage("[#report | member]")]
+pub async fn reportlog(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let mut report_id = None;
+    let mut reporter_id = None;
+    if let Some(id) = args.rest().strip_prefix('#') {
+        match id.parse::<u64>() {
+            Ok(id) => report_id = Some(id),
+            Err(_) => {
+                msg.channel_id
+                    .say(&ctx, "Expected a report like `#3`")
+                    .await?;
+                return Ok(());
+            }
+        }
+    } else if !args.is_empty() {
+        match Member::convert(ctx, msg.guild_id, Some(msg.channel_id), args.rest()).await {
+            Ok(member) => reporter_id = Some(member.user.id.0),
+            Err(_) => {
+                msg.channel_id
+                    .say(&ctx, "Could not find that member")
+                    .await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let storage = storage(ctx).await;
+    let entries: Vec<ReportAuditEntry> = storage
+        .get_report_audit_entries(guild_id.0, 100)
+        .await?
+        .into_iter()
+        .filter(|entry| report_id.is_none() || report_id == Some(entry.report_id))
+        .filter(|entry| reporter_id.is_none() || reporter_id == Some(entry.reporter_id))
+        .take(10)
+        .collect();
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.title("Reporters");
+                if entries.is_empty() {
+                    e.description("Nothing yet");
+                }
+                for entry in &entries {
+                    e.field(entry.time.format("%Y-%m-%d %H:%M UTC"), entry.describe(), false);
+                }
+                e
+            });
+            m
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[description = "Lists reported messages that are still open or claimed"]
+#[only_in(guilds)]
+#[num_args(0)]
+#[required_permissions("MANAGE_MESSAGES")]
+pub async fn reports(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap().0;
+    let storage = storage(ctx).await;
+    let reports = storage.get_active_reports(guild_id).await?;
+
+    // Embed descriptions top out at 4096 characters, so only the oldest fit
+    let lines: Vec<String> = reports
+        .iter()
+        .take(25)
+        .map(|report| {
+            format!(
+                "[#{}]({}) {} · <@{}> in <#{}>, {}",
+                report.id,
+                report.forward_link(guild_id),
+                report.status_line(),
+                report.author_id,
+                report.channel_id,
+                report.created.format("%Y-%m-%d %H:%M UTC")
+            )
+        })
+        .collect();
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.title(format!("Open reports ({})", reports.len()));
+                if lines.is_empty() {
+                    e.description("Nothing to look at");
+                } else {
+                    e.description(lines.join("\n"));
+                }
+                e
+            });
+            m
+        })
+        .await?;
+
+    Ok(())
+}
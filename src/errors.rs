@@ -1,6 +1,7 @@
 use derive_more::Display;
+use tracing::error;
 
-#[derive(Display)]
+#[derive(Debug, Display)]
 pub enum GompeiError {
     #[display(fmt = "Command Error: {}", _0)]
     CommandError(String),
@@ -15,6 +16,8 @@ pub enum GompeiError {
     GenericError(String),
 }
 
+impl std::error::Error for GompeiError {}
+
 impl From<Box<dyn std::error::Error>> for GompeiError {
     fn from(e: Box<dyn std::error::Error>) -> GompeiError {
         GompeiError::GenericError(e.to_string())
@@ -27,13 +30,14 @@ impl From<serenity::Error> for GompeiError {
         match e {
             BotLibErr::Gateway(e) => GompeiError::SerenityError(format!("Gateway Error: {:?}", e)),
             BotLibErr::Model(e) => GompeiError::SerenityError(format!("Model Error: {:?}", e)),
-            _ => GompeiError::SerenityError(format!("Unknown Error.")),
+            _ => GompeiError::SerenityError(String::from("Unknown Error.")),
         }
     }
 }
 
 impl From<redis::RedisError> for GompeiError {
-    fn from(_: redis::RedisError) -> GompeiError {
+    fn from(e: redis::RedisError) -> GompeiError {
+        error!("Redis error: {}", e);
         GompeiError::DatabaseError
     }
 }
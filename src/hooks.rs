@@ -35,7 +35,7 @@ pub async fn dispatch_error_hook(ctx: &Context, msg: &Message, error: DispatchEr
                         .say(&ctx, format!("Permission denied: {}", details))
                         .await.unwrap();
                 }
-                Reason::UserAndLog { user, log } => {
+                Reason::UserAndLog { user, .. } => {
                     msg.channel_id
                         .say(&ctx, format!("Permission denied: {}", user))
                         .await.unwrap();
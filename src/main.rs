@@ -1,5 +1,7 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 use serenity::{
     async_trait,
@@ -11,11 +13,13 @@ use serenity::{
     http::Http,
     model::gateway::Ready,
     model::{
-        channel::{Attachment, Message, MessageType, Reaction, ReactionType},
-        id::{ChannelId, UserId},
+        channel::{Message, Reaction},
+        guild::Guild,
+        id::{ChannelId, GuildId, UserId},
+        interactions::Interaction,
+        voice::VoiceState,
     },
     prelude::*,
-    utils::MessageBuilder,
 };
 
 use chrono::prelude::*;
@@ -28,9 +32,31 @@ pub mod errors;
 pub mod hooks;
 pub mod util;
 
-pub struct RedisConnection;
-impl TypeMapKey for RedisConnection {
-    type Value = redis::Connection;
+pub struct StorageContainer;
+impl TypeMapKey for StorageContainer {
+    type Value = Arc<dyn util::storage::Storage>;
+}
+
+pub struct LevelingConfigContainer;
+impl TypeMapKey for LevelingConfigContainer {
+    type Value = Arc<util::leveling::LevelingConfig>;
+}
+
+pub struct VoiceTrackerContainer;
+impl TypeMapKey for VoiceTrackerContainer {
+    type Value = Arc<std::sync::Mutex<util::voice::VoiceTracker>>;
+}
+
+pub struct AbuseTrackerContainer;
+impl TypeMapKey for AbuseTrackerContainer {
+    type Value = Arc<std::sync::Mutex<util::abuse::AbuseTracker>>;
+}
+
+/// A lock for each guild, held while one of its reports is made or changed, so reports of one
+/// message at the same time end up as one report
+pub struct ReportLockContainer;
+impl TypeMapKey for ReportLockContainer {
+    type Value = Arc<std::sync::Mutex<HashMap<u64, Arc<Mutex<()>>>>>;
 }
 
 use commands::fun::*;
@@ -43,7 +69,7 @@ use commands::staff::*;
 struct Meta;
 
 #[group]
-#[commands(rank, levels)]
+#[commands(rank, levels, cardtheme, activity)]
 struct Leveling;
 
 #[group]
@@ -51,86 +77,133 @@ struct Leveling;
 struct Fun;
 
 #[group]
-#[commands(clear, sendmsg, editmsg, reactmsg)]
+#[commands(
+    clear,
+    sendmsg,
+    editmsg,
+    reactmsg,
+    migratelevels,
+    levelup,
+    levelupmsg,
+    reward,
+    unreward,
+    rewards,
+    rewardmode,
+    resyncroles,
+    givexp,
+    takexp,
+    setxp,
+    setmessages,
+    resetxp,
+    resetguildxp,
+    xplog,
+    xpmultiplier,
+    noxp,
+    clearxpmultiplier,
+    xpmultipliers,
+    startseason,
+    endseason,
+    importlevels,
+    exportlevels,
+    xprule,
+    xprefusals,
+    reportchannel,
+    reportemoji,
+    reportanonymous,
+    reportlog,
+    reports
+)]
 struct Staff;
 
-struct Handler;
+struct Handler {
+    voice_xp_started: AtomicBool,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
-    async fn ready(&self, _ctx: Context, ready: Ready) {
-        info!("Logged into Discord as {}", ready.user.name)
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        info!("Logged into Discord as {}", ready.user.name);
+        util::voice::start_voice_xp(ctx, &self.voice_xp_started);
     }
 
-    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
-        if let None = reaction.guild_id {
-            return;
+    async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: bool) {
+        let tracker = util::data::voice_tracker(&ctx).await;
+        tracker.lock().unwrap().load_guild(&guild);
+    }
+
+    async fn voice_state_update(
+        &self,
+        ctx: Context,
+        guild_id: Option<GuildId>,
+        _old: Option<VoiceState>,
+        new: VoiceState,
+    ) {
+        if let Some(guild_id) = guild_id {
+            let tracker = util::data::voice_tracker(&ctx).await;
+            tracker.lock().unwrap().update(
+                guild_id,
+                new.user_id,
+                util::voice::VoiceMember::from_state(&new),
+            );
         }
+    }
 
-        if let Ok(chan) = env::var("LOGGING_CHANNEL") {
-            let channel_id = ChannelId(chan.parse::<u64>().unwrap());
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        let guild_id = match reaction.guild_id {
+            Some(guild_id) => guild_id,
+            None => return,
+        };
 
-            let escalate_emoji = vec!["❗", "‼️", "⁉️", "❕"];
-            match reaction.emoji {
-                ReactionType::Unicode(ref tmp) => {
-                    if escalate_emoji.contains(&tmp.as_str()) {
-                        let message = reaction
-                            .channel_id
-                            .message(&ctx, reaction.message_id)
-                            .await
-                            .unwrap();
-                        if message.embeds.len() == 0 {
-                            channel_id
-                                .send_message(&ctx.http, |m| {
-                                    m.content(format!(
-                                        "Message forwarded by <@{}> from <#{}>",
-                                        reaction.clone().user_id.unwrap(),
-                                        reaction.channel_id.0
-                                    ));
-                                    m.embed(|e| {
-                                        e.author(|a| {
-                                            a.name(message.clone().author.name);
-                                            a.icon_url(message.clone().author.face());
-                                            a
-                                        });
-                                        e.description(message.clone().content);
-                                        e.field("Link", message.link(), true);
-                                        e
-                                    });
-                                    m
-                                })
-                                .await
-                                .unwrap();
-                        } else {
-                            channel_id
-                                .send_message(&ctx.http, |m| {
-                                    m.content(format!(
-                                        "Message forwarded by <@{}> from <#{}>\n\n",
-                                        reaction.clone().user_id.unwrap(),
-                                        reaction.channel_id.0
-
-                                    ));
-                                    m.set_embed(message.embeds[0].clone().into());
-                                    m
-                                })
-                                .await
-                                .unwrap();
-                        }
-                        reaction.delete(&ctx.http).await.unwrap();
-                    } else {
-                        return;
-                    }
+        let storage = util::data::storage(&ctx).await;
+        match util::escalation::get_escalation_emoji(guild_id.0, storage.as_ref()).await {
+            Ok(emoji) => {
+                if !emoji
+                    .iter()
+                    .any(|e| util::escalation::emoji_matches(e, &reaction.emoji))
+                {
+                    return;
                 }
-                _ => {
+            }
+            Err(e) => {
+                error!("Error getting escalation emoji: {:?}", e);
+                return;
+            }
+        }
+
+        let channel_id =
+            match util::escalation::get_escalation_channel(&ctx, guild_id, storage.as_ref()).await {
+                Ok(Some(channel_id)) => channel_id,
+                Ok(None) => return,
+                Err(e) => {
+                    error!("Error getting escalation channel: {:?}", e);
                     return;
                 }
+            };
+
+        if let Err(e) = util::escalation::forward_report(
+            &ctx,
+            guild_id,
+            &reaction,
+            channel_id,
+            storage.as_ref(),
+        )
+        .await
+        {
+            error!("Error forwarding report: {:?}", e);
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::MessageComponent(component) = interaction {
+            if let Err(e) = util::reports::handle_report_button(&ctx, &component).await {
+                error!("Error updating report: {:?}", e);
             }
         }
     }
 
     #[instrument(skip(self, ctx))]
     async fn message(&self, ctx: Context, msg: Message) {
-        if let None = msg.guild_id {
+        if msg.guild_id.is_none() {
             // It's in a DM
             if !msg.author.bot {
             if let Ok(chan) = env::var("PM_CHANNEL") {
@@ -170,7 +243,7 @@ impl EventHandler for Handler {
                         let our_id = ctx.http.get_current_user().await.unwrap().id.0;
                         if original_message.author.id == our_id {
                             // It's our message
-                            if original_message.embeds.len() > 0 {
+                            if !original_message.embeds.is_empty() {
                                 // It's got an embed, probably one of our PM ones
                                 let pm_embed = original_message.embeds[0].clone();
                                 if let Some(footer) = pm_embed.footer {
@@ -191,26 +264,80 @@ impl EventHandler for Handler {
             .content
             .starts_with(&env::var("DISCORD_PREFIX").unwrap())
         {
-            if !msg.author.bot {
-                let mut bot_data = ctx.data.write().await;
-                let mut redis_conn = bot_data.get_mut::<RedisConnection>().unwrap();
-                match util::leveling::get_user_level(msg.author.id.0, &mut redis_conn) {
-                    Ok(data) => {
-                        let time_since_last_msg = data.last_msg - Utc::now();
-                        if time_since_last_msg.num_minutes() < -1 {
-                            let mut new_data = data.clone();
-                            new_data.msg_count += 1;
-                            new_data.xp += 1;
-                            new_data.last_msg = Utc::now();
-                            if let Err(e) = util::leveling::set_user_level(
+            if let (false, Some(guild_id)) = (msg.author.bot, msg.guild_id) {
+                // XP is only tracked per guild, so DMs don't earn any
+                let guild_id = guild_id.0;
+                let storage = util::data::storage(&ctx).await;
+                let config = util::data::leveling_config(&ctx).await;
+                let mut multiplier =
+                    match util::multipliers::get_message_multiplier(&ctx, &msg, storage.as_ref())
+                        .await
+                    {
+                        Ok(multiplier) => multiplier,
+                        Err(e) => {
+                            error!("Error getting XP multiplier: {:?}", e);
+                            1.0
+                        }
+                    };
+                let now = Utc::now();
+                // Only messages that would earn XP are worth checking, so not ones in no-XP
+                // channels or sent during the cooldown
+                if multiplier > 0.0 {
+                    let on_cooldown = match storage.get_user_level(guild_id, msg.author.id.0).await
+                    {
+                        Ok(data) => config.on_cooldown(&data, now),
+                        Err(e) => {
+                            error!("Error getting level data: {:?}", e);
+                            false
+                        }
+                    };
+                    if !on_cooldown {
+                        match util::abuse::check_message(&ctx, &msg, storage.as_ref()).await {
+                            Ok(true) => (),
+                            Ok(false) => multiplier = 0.0,
+                            Err(e) => error!("Error checking message for XP abuse: {:?}", e),
+                        }
+                    }
+                }
+                match util::leveling::award_message_xp(
+                    storage.as_ref(),
+                    &config,
+                    guild_id,
+                    msg.author.id.0,
+                    now,
+                    multiplier,
+                )
+                .await
+                {
+                    Ok(Some((old_data, new_data))) => {
+                        if new_data.level > old_data.level {
+                            if let Err(e) = util::leveling::announce_level_up(
+                                &ctx,
+                                msg.guild_id.unwrap(),
+                                msg.author.id,
+                                Some(msg.channel_id),
+                                old_data.level,
+                                new_data.level,
+                                storage.as_ref(),
+                            )
+                            .await
+                            {
+                                error!("Error announcing level up: {:?}", e);
+                            }
+                            if let Err(e) = util::rewards::sync_member_rewards(
+                                &ctx,
+                                msg.guild_id.unwrap(),
                                 msg.author.id.0,
-                                &mut redis_conn,
-                                new_data,
-                            ) {
-                                error!("{:?}", e);
+                                new_data.level,
+                                storage.as_ref(),
+                            )
+                            .await
+                            {
+                                error!("Error granting level rewards: {:?}", e);
                             }
                         }
                     }
+                    Ok(None) => (),
                     Err(e) => {
                         error!("Error computing levels: {:?}", e);
                         return;
@@ -250,7 +377,7 @@ async fn main() {
     let framework = StandardFramework::new()
         .configure(|c| {
             c.owners(owners)
-                .prefix(&env::var("DISCORD_PREFIX").expect("No prefix in environment"))
+                .prefix(env::var("DISCORD_PREFIX").expect("No prefix in environment"))
         })
         .on_dispatch_error(hooks::dispatch_error_hook)
         .after(hooks::after_cmd)
@@ -261,14 +388,16 @@ async fn main() {
         .group(&STAFF_GROUP);
     let mut client = Client::builder(&token)
         .framework(framework)
-        .event_handler(Handler)
+        .event_handler(Handler {
+            voice_xp_started: AtomicBool::new(false),
+        })
         .intents(GatewayIntents::all())
         .await
         .expect("Could not create discord client");
 
     {
         let mut data = client.data.write().await;
-        let con = match util::data::get_redis_connection() {
+        let conn = match util::data::get_redis_connection().await {
             Ok(red) => red,
             Err(err) => {
                 error!("Could not obtain a redis connection: {:?}", err);
@@ -276,7 +405,15 @@ async fn main() {
             }
         };
 
-        data.insert::<RedisConnection>(con)
+        let config = util::leveling::LevelingConfig::from_env();
+        data.insert::<StorageContainer>(Arc::new(util::storage::RedisStorage::new(
+            conn,
+            config.curve,
+        )));
+        data.insert::<LevelingConfigContainer>(Arc::new(config));
+        data.insert::<VoiceTrackerContainer>(Arc::default());
+        data.insert::<AbuseTrackerContainer>(Arc::default());
+        data.insert::<ReportLockContainer>(Arc::default());
     }
 
     info!("Starting client");
@@ -0,0 +1,539 @@
+//! Rules against farming XP
+//!
+//! On top of the cooldown, messages can be refused XP for being too short, repeating the
+//! member's last message, or being nothing but emoji or links. Posting fast for a sustained
+//! stretch is flagged for staff without refusing anything. Every refusal and flag is logged
+//! against the member with a reason code.
+//!
+//! Every rule is off until staff turn it on with `xprule`. Only messages that would otherwise
+//! earn XP are checked, so messages sent during the cooldown don't count towards any of them.
+use chrono::{prelude::*, Duration};
+use serde::{Deserialize, Serialize};
+use serenity::{client::Context, model::channel::Message};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+use crate::util::data::abuse_tracker;
+use crate::util::storage::{Storage, StorageResult};
+
+/// Why a message didn't earn XP, or why a member was flagged
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbuseReason {
+    TooShort,
+    Repeated,
+    EmojiOnly,
+    LinksOnly,
+    /// Flagged only; the message still earns XP
+    HighRate,
+}
+
+impl AbuseReason {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AbuseReason::TooShort => "too_short",
+            AbuseReason::Repeated => "repeated",
+            AbuseReason::EmojiOnly => "emoji_only",
+            AbuseReason::LinksOnly => "links_only",
+            AbuseReason::HighRate => "high_rate",
+        }
+    }
+}
+
+/// A logged refusal or flag
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AbuseEntry {
+    pub reason: AbuseReason,
+    pub channel_id: u64,
+    pub time: DateTime<Utc>,
+}
+
+/// A guild's anti-abuse settings
+#[derive(Clone, Debug, PartialEq)]
+pub struct AbuseRules {
+    /// Messages with fewer characters than this earn nothing. Zero turns the rule off.
+    pub min_length: usize,
+    pub block_repeats: bool,
+    pub block_emoji_only: bool,
+    pub block_links_only: bool,
+    /// Flag members who post more than this many messages within `rate_window`. Zero turns the
+    /// rule off.
+    pub rate_limit: usize,
+    pub rate_window: Duration,
+}
+
+impl Default for AbuseRules {
+    fn default() -> AbuseRules {
+        AbuseRules {
+            min_length: 0,
+            block_repeats: false,
+            block_emoji_only: false,
+            block_links_only: false,
+            rate_limit: 0,
+            rate_window: Duration::seconds(60),
+        }
+    }
+}
+
+/// The rules staff can change, as named in settings and commands
+pub const RULE_NAMES: [&str; 5] = ["min_length", "repeats", "emoji_only", "links_only", "rate"];
+
+/// Checks a rule's value is valid, returning it as it should be stored
+///
+/// `min_length` takes a number, `rate` takes `messages/seconds` or `off`, and the others take
+/// `on` or `off`.
+pub fn parse_rule(name: &str, value: &str) -> Option<String> {
+    match name {
+        "min_length" => value.parse::<usize>().ok().map(|v| v.to_string()),
+        "repeats" | "emoji_only" | "links_only" => match value {
+            "on" | "off" => Some(value.to_string()),
+            _ => None,
+        },
+        "rate" => {
+            if value == "off" {
+                return Some(String::from("0/60"));
+            }
+            let (limit, window) = value.split_once('/')?;
+            let (limit, window) = (limit.parse::<usize>().ok()?, window.parse::<i64>().ok()?);
+            if window <= 0 {
+                return None;
+            }
+            Some(format!("{}/{}", limit, window))
+        }
+        _ => None,
+    }
+}
+
+/// The settings the rules are stored under, in the same order as [`RULE_NAMES`]
+const RULE_SETTINGS: [&str; 5] = [
+    "abuse_min_length",
+    "abuse_repeats",
+    "abuse_emoji_only",
+    "abuse_links_only",
+    "abuse_rate",
+];
+
+/// Gets a guild's rules, falling back to the defaults for anything unset
+///
+/// This runs for every message, so all the rules are read in one go.
+pub async fn get_abuse_rules(guild_id: u64, storage: &dyn Storage) -> StorageResult<AbuseRules> {
+    let mut rules = AbuseRules::default();
+    let mut settings = storage
+        .get_settings(guild_id, &RULE_SETTINGS)
+        .await?
+        .into_iter();
+    let mut next = || settings.next().flatten();
+
+    if let Some(v) = next() {
+        rules.min_length = v.parse().unwrap_or(rules.min_length);
+    }
+    for flag in [
+        &mut rules.block_repeats,
+        &mut rules.block_emoji_only,
+        &mut rules.block_links_only,
+    ] {
+        if let Some(v) = next() {
+            *flag = v != "off";
+        }
+    }
+    if let Some(v) = next() {
+        if let Some((limit, window)) = v.split_once('/') {
+            if let (Ok(limit), Ok(window)) = (limit.parse(), window.parse()) {
+                rules.rate_limit = limit;
+                rules.rate_window = Duration::seconds(window);
+            }
+        }
+    }
+
+    Ok(rules)
+}
+
+/// Stores one of a guild's rules, which should already have been through [`parse_rule`]
+pub async fn set_abuse_rule(
+    guild_id: u64,
+    name: &str,
+    value: &str,
+    storage: &dyn Storage,
+) -> StorageResult<()> {
+    storage
+        .set_setting(guild_id, &format!("abuse_{}", name), value)
+        .await
+}
+
+/// Whether a character is part of an emoji, including the joiners and modifiers between them
+fn is_emoji_char(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF // Pictographs, emoticons, flags, skin tones
+        | 0x2190..=0x21FF // Arrows
+        | 0x2300..=0x23FF // Technical, like ⌚ and ⏩
+        | 0x2460..=0x24FF // Enclosed alphanumerics
+        | 0x2500..=0x27BF // Shapes, symbols and dingbats
+        | 0x2900..=0x297F
+        | 0x2B00..=0x2BFF
+        | 0x3030 | 0x303D | 0x3297 | 0x3299
+        | 0x200D // Zero width joiner
+        | 0xFE0E..=0xFE0F // Variation selectors
+        | 0x20E3 // Keycap
+        | 0xE0020..=0xE007F // Tags
+    )
+}
+
+/// Whether a word is a Discord custom emoji like `<:goat:123>` or `<a:goat:123>`
+fn is_custom_emoji(word: &str) -> bool {
+    let inner = match word.strip_prefix("<a:").or_else(|| word.strip_prefix("<:")) {
+        Some(rest) => match rest.strip_suffix('>') {
+            Some(inner) => inner,
+            None => return false,
+        },
+        None => return false,
+    };
+    match inner.split_once(':') {
+        Some((name, id)) => !name.is_empty() && id.parse::<u64>().is_ok(),
+        None => false,
+    }
+}
+
+/// Whether a message is nothing but emoji
+pub fn is_emoji_only(content: &str) -> bool {
+    let mut any = false;
+    for word in content.split_whitespace() {
+        // Custom emoji are often written back to back
+        let mut rest = word;
+        while !rest.is_empty() {
+            if rest.starts_with('<') {
+                match rest.find('>') {
+                    Some(end) if is_custom_emoji(&rest[..=end]) => rest = &rest[end + 1..],
+                    _ => return false,
+                }
+            } else {
+                let c = rest.chars().next().unwrap();
+                if !is_emoji_char(c) {
+                    return false;
+                }
+                rest = &rest[c.len_utf8()..];
+            }
+            any = true;
+        }
+    }
+
+    any
+}
+
+/// Whether a message is nothing but links
+pub fn is_links_only(content: &str) -> bool {
+    let mut words = content.split_whitespace().peekable();
+    words.peek().is_some()
+        && words.all(|word| {
+            let word = word.trim_start_matches('<').trim_end_matches('>');
+            word.starts_with("http://") || word.starts_with("https://")
+        })
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.trim().to_lowercase().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// What's remembered about a member's recent messages
+#[derive(Debug)]
+struct MemberHistory {
+    last_content: Option<u64>,
+    recent: VecDeque<DateTime<Utc>>,
+    last_seen: DateTime<Utc>,
+}
+
+/// Members who haven't posted for this long are forgotten, which only matters for rate windows
+/// longer than it
+const MEMBER_IDLE_TIME: i64 = 60 * 60;
+
+/// The outcome of checking a message against the rules
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AbuseCheck {
+    /// Set if the message shouldn't earn XP
+    pub refusal: Option<AbuseReason>,
+    /// Set if the member has just started posting faster than the rate limit
+    pub flagged: bool,
+}
+
+/// Recent messages from everyone, for the rules that look at more than one message
+#[derive(Debug, Default)]
+pub struct AbuseTracker {
+    members: HashMap<(u64, u64), MemberHistory>,
+    last_pruned: Option<DateTime<Utc>>,
+}
+
+impl AbuseTracker {
+    /// Checks a message against the rules and remembers it for the next check
+    ///
+    /// Messages `with_media`, meaning attachments or stickers, aren't held to the length, emoji
+    /// and link rules, since their text is often empty.
+    pub fn check(
+        &mut self,
+        rules: &AbuseRules,
+        guild_id: u64,
+        user_id: u64,
+        content: &str,
+        with_media: bool,
+        now: DateTime<Utc>,
+    ) -> AbuseCheck {
+        self.prune(now);
+        let history = self
+            .members
+            .entry((guild_id, user_id))
+            .or_insert_with(|| MemberHistory {
+                last_content: None,
+                recent: VecDeque::new(),
+                last_seen: now,
+            });
+        history.last_seen = now;
+
+        let hash = content_hash(content);
+        // Two pictures in a row aren't a repeat just because neither has any text
+        let repeated =
+            history.last_content == Some(hash) && !(with_media && content.trim().is_empty());
+        history.last_content = Some(hash);
+
+        while let Some(oldest) = history.recent.front() {
+            if now - *oldest > rules.rate_window {
+                history.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+        history.recent.push_back(now);
+        // Only flag as the limit is crossed, not for every message after it
+        let flagged = rules.rate_limit > 0 && history.recent.len() == rules.rate_limit + 1;
+        // Nothing older than the window is ever needed
+        if history.recent.len() > rules.rate_limit + 1 {
+            history.recent.pop_front();
+        }
+
+        let refusal = if !with_media && rules.block_emoji_only && is_emoji_only(content) {
+            Some(AbuseReason::EmojiOnly)
+        } else if !with_media && rules.block_links_only && is_links_only(content) {
+            Some(AbuseReason::LinksOnly)
+        } else if !with_media && content.trim().chars().count() < rules.min_length {
+            Some(AbuseReason::TooShort)
+        } else if rules.block_repeats && repeated {
+            Some(AbuseReason::Repeated)
+        } else {
+            None
+        };
+
+        AbuseCheck { refusal, flagged }
+    }
+
+    /// Forgets idle members, at most once per [`MEMBER_IDLE_TIME`]
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let idle_time = Duration::seconds(MEMBER_IDLE_TIME);
+        match self.last_pruned {
+            Some(last_pruned) if now - last_pruned < idle_time => {}
+            _ => {
+                self.members
+                    .retain(|_, history| now - history.last_seen < idle_time);
+                self.last_pruned = Some(now);
+            }
+        }
+    }
+}
+
+/// Checks a guild message against its rules, logging any refusal or flag
+///
+/// Returns whether the message may earn XP.
+pub async fn check_message(
+    ctx: &Context,
+    msg: &Message,
+    storage: &dyn Storage,
+) -> StorageResult<bool> {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => return Ok(false),
+    };
+    let rules = get_abuse_rules(guild_id, storage).await?;
+    let check = abuse_tracker(ctx).await.lock().unwrap().check(
+        &rules,
+        guild_id,
+        msg.author.id.0,
+        &msg.content,
+        !msg.attachments.is_empty() || !msg.stickers.is_empty(),
+        msg.timestamp,
+    );
+
+    let reasons = check
+        .refusal
+        .into_iter()
+        .chain(check.flagged.then_some(AbuseReason::HighRate));
+    for reason in reasons {
+        let entry = AbuseEntry {
+            reason,
+            channel_id: msg.channel_id.0,
+            time: msg.timestamp,
+        };
+        storage
+            .add_abuse_entry(guild_id, msg.author.id.0, &entry)
+            .await?;
+    }
+
+    Ok(check.refusal.is_none())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Every rule turned on
+    fn strict_rules() -> AbuseRules {
+        AbuseRules {
+            min_length: 3,
+            block_repeats: true,
+            block_emoji_only: true,
+            block_links_only: true,
+            rate_limit: 20,
+            rate_window: Duration::seconds(60),
+        }
+    }
+
+    fn check(tracker: &mut AbuseTracker, content: &str, secs: i64) -> AbuseCheck {
+        tracker.check(
+            &strict_rules(),
+            1,
+            2,
+            content,
+            false,
+            Utc.timestamp_opt(secs, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn emoji_only() {
+        assert!(is_emoji_only("😂"));
+        assert!(is_emoji_only("👍🏽 ❤️"));
+        assert!(is_emoji_only("<:goat:123456><a:dance:789>"));
+        assert!(!is_emoji_only("lol 😂"));
+        assert!(!is_emoji_only("<@123456>"));
+        assert!(!is_emoji_only(""));
+    }
+
+    #[test]
+    fn links_only() {
+        assert!(is_links_only("https://wpi.edu <https://example.com>"));
+        assert!(!is_links_only("look https://wpi.edu"));
+        assert!(!is_links_only("   "));
+    }
+
+    #[test]
+    fn refuses_short_and_repeated_messages() {
+        let mut tracker = AbuseTracker::default();
+        assert_eq!(
+            check(&mut tracker, "ok", 0).refusal,
+            Some(AbuseReason::TooShort)
+        );
+        assert_eq!(check(&mut tracker, "hello there", 100).refusal, None);
+        assert_eq!(
+            check(&mut tracker, "Hello there ", 200).refusal,
+            Some(AbuseReason::Repeated)
+        );
+        assert_eq!(check(&mut tracker, "something else", 300).refusal, None);
+    }
+
+    #[test]
+    fn rules_can_be_turned_off() {
+        let rules = AbuseRules {
+            min_length: 0,
+            block_emoji_only: false,
+            ..strict_rules()
+        };
+        let mut tracker = AbuseTracker::default();
+        let now = Utc::now();
+        assert_eq!(tracker.check(&rules, 1, 2, "😂", false, now).refusal, None);
+    }
+
+    #[test]
+    fn media_skips_content_rules() {
+        let mut tracker = AbuseTracker::default();
+        let now = Utc::now();
+        let rules = strict_rules();
+        assert_eq!(tracker.check(&rules, 1, 2, "", true, now).refusal, None);
+        assert_eq!(tracker.check(&rules, 1, 2, "", true, now).refusal, None);
+        assert_eq!(tracker.check(&rules, 1, 2, "😂", true, now).refusal, None);
+        assert_eq!(
+            tracker.check(&rules, 1, 2, "😂", false, now).refusal,
+            Some(AbuseReason::EmojiOnly)
+        );
+    }
+
+    #[test]
+    fn idle_members_are_forgotten() {
+        let mut tracker = AbuseTracker::default();
+        check(&mut tracker, "hello there", 0);
+        check(&mut tracker, "hello there", 1);
+        assert_eq!(tracker.members.len(), 1);
+
+        tracker.check(
+            &strict_rules(),
+            1,
+            3,
+            "someone else",
+            false,
+            Utc.timestamp_opt(MEMBER_IDLE_TIME + 10, 0).unwrap(),
+        );
+        assert_eq!(tracker.members.len(), 1);
+        assert!(tracker.members.contains_key(&(1, 3)));
+    }
+
+    #[tokio::test]
+    async fn rules_are_read_from_settings() {
+        let storage = crate::util::storage::MemoryStorage::new();
+        assert_eq!(
+            get_abuse_rules(1, &storage).await.unwrap(),
+            AbuseRules::default()
+        );
+
+        set_abuse_rule(1, "emoji_only", "on", &storage)
+            .await
+            .unwrap();
+        set_abuse_rule(1, "rate", "5/10", &storage).await.unwrap();
+
+        let rules = get_abuse_rules(1, &storage).await.unwrap();
+        assert!(rules.block_emoji_only);
+        assert!(!rules.block_links_only);
+        assert_eq!(
+            (rules.rate_limit, rules.rate_window),
+            (5, Duration::seconds(10))
+        );
+    }
+
+    #[test]
+    fn rules_start_off() {
+        let mut tracker = AbuseTracker::default();
+        let rules = AbuseRules::default();
+        let now = Utc::now();
+        for _ in 0..30 {
+            assert_eq!(
+                tracker.check(&rules, 1, 2, "😂", false, now),
+                AbuseCheck::default()
+            );
+        }
+    }
+
+    #[test]
+    fn flags_once_when_rate_is_crossed() {
+        let mut tracker = AbuseTracker::default();
+        let flags: Vec<bool> = (0..30)
+            .map(|i| check(&mut tracker, &format!("message number {}", i), i).flagged)
+            .collect();
+        assert_eq!(flags.iter().filter(|f| **f).count(), 1);
+        assert!(flags[20]);
+    }
+
+    #[test]
+    fn rule_values_are_checked() {
+        assert_eq!(parse_rule("min_length", "5"), Some(String::from("5")));
+        assert_eq!(parse_rule("repeats", "maybe"), None);
+        assert_eq!(parse_rule("rate", "10/30"), Some(String::from("10/30")));
+        assert_eq!(parse_rule("rate", "10/0"), None);
+        assert_eq!(parse_rule("colour", "on"), None);
+    }
+}
@@ -0,0 +1,289 @@
+//! Daily XP and message history, and charts of it
+//!
+//! Every award is also added to the member's and the guild's totals for the day through
+//! [`Storage::record_activity`]. Charts are drawn the same way as rank cards, with the bundled
+//! fonts.
+use chrono::{prelude::*, Duration};
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_line_segment_mut, draw_text_mut, text_size};
+use imageproc::rect::Rect;
+use rusttype::{Font, Scale};
+use std::io::Cursor;
+
+use crate::util::card::{FONT_BOLD, FONT_REGULAR};
+use crate::util::storage::{Storage, StorageResult};
+
+/// The longest history that can be asked for
+pub const MAX_DAYS: i64 = 365;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActivityDay {
+    pub date: NaiveDate,
+    pub xp: u32,
+    pub messages: u32,
+}
+
+/// Gets the last `days` days of history up to and including `today`, oldest first, for one
+/// member or the whole guild if `user_id` is `None`
+pub async fn get_activity_days(
+    guild_id: u64,
+    user_id: Option<u64>,
+    days: i64,
+    today: NaiveDate,
+    storage: &dyn Storage,
+) -> StorageResult<Vec<ActivityDay>> {
+    let dates: Vec<NaiveDate> = (0..days.clamp(1, MAX_DAYS))
+        .rev()
+        .map(|ago| today - Duration::days(ago))
+        .collect();
+    let totals = storage.get_activity(guild_id, user_id, &dates).await?;
+
+    Ok(dates
+        .into_iter()
+        .zip(totals)
+        .map(|(date, (xp, messages))| ActivityDay { date, xp, messages })
+        .collect())
+}
+
+/// Draws values as a row of block characters, scaled to the largest
+pub fn sparkline(values: &[u32]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().copied().max().unwrap_or(0);
+    values
+        .iter()
+        .map(|v| {
+            if max == 0 {
+                BLOCKS[0]
+            } else {
+                BLOCKS[(*v as u64 * (BLOCKS.len() as u64 - 1) / max as u64) as usize]
+            }
+        })
+        .collect()
+}
+
+const CHART_WIDTH: u32 = 900;
+const CHART_HEIGHT: u32 = 400;
+/// Space around the plot for the title and labels
+const PLOT_LEFT: i32 = 80;
+const PLOT_RIGHT: i32 = CHART_WIDTH as i32 - 80;
+const PLOT_TOP: i32 = 70;
+const PLOT_BOTTOM: i32 = CHART_HEIGHT as i32 - 50;
+
+const BACKGROUND: Rgba<u8> = Rgba([35, 39, 42, 255]);
+const GRID: Rgba<u8> = Rgba([72, 75, 78, 255]);
+const TEXT: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const XP_COLOR: Rgba<u8> = Rgba([98, 211, 245, 255]);
+const MESSAGES_COLOR: Rgba<u8> = Rgba([245, 166, 35, 255]);
+
+/// Draws one series as a line, scaled so its largest value reaches the top of the plot
+fn draw_series(image: &mut RgbaImage, values: &[u32], color: Rgba<u8>) {
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let step = if values.len() > 1 {
+        (PLOT_RIGHT - PLOT_LEFT) as f32 / (values.len() - 1) as f32
+    } else {
+        0.0
+    };
+    let point = |i: usize, v: u32| {
+        (
+            PLOT_LEFT as f32 + step * i as f32,
+            PLOT_BOTTOM as f32 - (PLOT_BOTTOM - PLOT_TOP) as f32 * v as f32 / max,
+        )
+    };
+
+    for (i, pair) in values.windows(2).enumerate() {
+        let (start, end) = (point(i, pair[0]), point(i + 1, pair[1]));
+        // Two pixels thick so it shows up in Discord's preview
+        draw_line_segment_mut(image, start, end, color);
+        draw_line_segment_mut(image, (start.0, start.1 - 1.0), (end.0, end.1 - 1.0), color);
+    }
+    if values.len() == 1 {
+        let (x, y) = point(0, values[0]);
+        draw_filled_rect_mut(
+            image,
+            Rect::at(x as i32 - 2, y as i32 - 2).of_size(5, 5),
+            color,
+        );
+    }
+}
+
+/// Draws a line chart of daily XP and messages and encodes it as a PNG
+///
+/// XP and messages are each scaled to their own maximum, which is labelled on the left and right
+/// axes respectively.
+pub fn render_activity_chart(
+    title: &str,
+    days: &[ActivityDay],
+) -> Result<Vec<u8>, image::ImageError> {
+    let regular = Font::try_from_bytes(FONT_REGULAR).expect("Bundled font is valid");
+    let bold = Font::try_from_bytes(FONT_BOLD).expect("Bundled font is valid");
+    let label = Scale::uniform(18.0);
+
+    let mut image = RgbaImage::from_pixel(CHART_WIDTH, CHART_HEIGHT, BACKGROUND);
+    draw_text_mut(
+        &mut image,
+        TEXT,
+        PLOT_LEFT,
+        20,
+        Scale::uniform(28.0),
+        &bold,
+        title,
+    );
+
+    // Legend, top right
+    let mut right = PLOT_RIGHT;
+    for (text, color) in &[("Messages", MESSAGES_COLOR), ("XP", XP_COLOR)] {
+        let (width, _) = text_size(label, &regular, text);
+        right -= width;
+        draw_text_mut(&mut image, *color, right, 28, label, &regular, text);
+        right -= 16;
+        draw_filled_rect_mut(&mut image, Rect::at(right, 32).of_size(10, 10), *color);
+        right -= 24;
+    }
+
+    // Gridlines at quarters
+    for quarter in 0..=4 {
+        let y = PLOT_BOTTOM - (PLOT_BOTTOM - PLOT_TOP) * quarter / 4;
+        draw_line_segment_mut(
+            &mut image,
+            (PLOT_LEFT as f32, y as f32),
+            (PLOT_RIGHT as f32, y as f32),
+            GRID,
+        );
+    }
+
+    let xp: Vec<u32> = days.iter().map(|d| d.xp).collect();
+    let messages: Vec<u32> = days.iter().map(|d| d.messages).collect();
+    draw_series(&mut image, &messages, MESSAGES_COLOR);
+    draw_series(&mut image, &xp, XP_COLOR);
+
+    // Axis labels: the maximum of each series, and the first and last dates
+    let max_xp = xp.iter().copied().max().unwrap_or(0).to_string();
+    let (width, _) = text_size(label, &regular, &max_xp);
+    draw_text_mut(
+        &mut image,
+        XP_COLOR,
+        PLOT_LEFT - width - 10,
+        PLOT_TOP - 9,
+        label,
+        &regular,
+        &max_xp,
+    );
+    let max_messages = messages.iter().copied().max().unwrap_or(0).to_string();
+    draw_text_mut(
+        &mut image,
+        MESSAGES_COLOR,
+        PLOT_RIGHT + 10,
+        PLOT_TOP - 9,
+        label,
+        &regular,
+        &max_messages,
+    );
+    draw_text_mut(
+        &mut image,
+        TEXT,
+        PLOT_LEFT - 20,
+        PLOT_BOTTOM - 9,
+        label,
+        &regular,
+        "0",
+    );
+    if let (Some(first), Some(last)) = (days.first(), days.last()) {
+        let first = first.date.format("%b %-d").to_string();
+        let last = last.date.format("%b %-d").to_string();
+        draw_text_mut(
+            &mut image,
+            TEXT,
+            PLOT_LEFT,
+            PLOT_BOTTOM + 15,
+            label,
+            &regular,
+            &first,
+        );
+        let (width, _) = text_size(label, &regular, &last);
+        draw_text_mut(
+            &mut image,
+            TEXT,
+            PLOT_RIGHT - width,
+            PLOT_BOTTOM + 15,
+            label,
+            &regular,
+            &last,
+        );
+    }
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgba8(image).write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+
+    Ok(png)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::storage::MemoryStorage;
+
+    #[test]
+    fn sparkline_scales_to_max() {
+        assert_eq!(sparkline(&[0, 7, 14]), "▁▄█");
+        assert_eq!(sparkline(&[0, 0]), "▁▁");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[tokio::test]
+    async fn history_is_per_member_and_guild() {
+        let storage = MemoryStorage::new();
+        let today = NaiveDate::from_ymd_opt(2021, 9, 3).unwrap();
+        storage.record_activity(1, 2, today, 10, 1).await.unwrap();
+        storage
+            .record_activity(1, 3, today - Duration::days(2), 5, 1)
+            .await
+            .unwrap();
+
+        let member = get_activity_days(1, Some(2), 3, today, &storage)
+            .await
+            .unwrap();
+        let xp: Vec<u32> = member.iter().map(|d| d.xp).collect();
+        assert_eq!(xp, vec![0, 0, 10]);
+        assert_eq!(member[0].date, NaiveDate::from_ymd_opt(2021, 9, 1).unwrap());
+
+        let guild = get_activity_days(1, None, 3, today, &storage)
+            .await
+            .unwrap();
+        let messages: Vec<u32> = guild.iter().map(|d| d.messages).collect();
+        assert_eq!(messages, vec![1, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn reset_clears_history() {
+        let storage = MemoryStorage::new();
+        let today = NaiveDate::from_ymd_opt(2021, 9, 3).unwrap();
+        storage.record_activity(1, 2, today, 10, 1).await.unwrap();
+        storage.record_activity(4, 2, today, 10, 1).await.unwrap();
+
+        storage.reset_guild_levels(1, &[]).await.unwrap();
+        assert_eq!(
+            storage.get_activity(1, None, &[today]).await.unwrap(),
+            vec![(0, 0)]
+        );
+        assert_eq!(
+            storage.get_activity(4, Some(2), &[today]).await.unwrap(),
+            vec![(10, 1)]
+        );
+    }
+
+    #[test]
+    fn renders_png() {
+        let days: Vec<ActivityDay> = (1..=30)
+            .map(|day| ActivityDay {
+                date: NaiveDate::from_ymd_opt(2021, 9, day).unwrap(),
+                xp: day * 3,
+                messages: day % 7,
+            })
+            .collect();
+        let png = render_activity_chart("Activity", &days).unwrap();
+
+        let decoded = image::load_from_memory(&png).unwrap();
+        assert_eq!(decoded.width(), CHART_WIDTH);
+        assert_eq!(decoded.height(), CHART_HEIGHT);
+    }
+}
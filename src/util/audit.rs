@@ -0,0 +1,216 @@
+//! Staff changes to member XP
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::util::leveling::LevelData;
+
+/// A manual change staff can make to a member's totals
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum XpChange {
+    Give(u32),
+    Take(u32),
+    Set(u32),
+    SetMessages(u32),
+    Reset,
+}
+
+impl XpChange {
+    /// Applies the change to a member's level data
+    ///
+    /// The level itself is left for storage to work out from the new XP.
+    pub fn apply(&self, data: &mut LevelData) {
+        match *self {
+            XpChange::Give(xp) => data.xp = data.xp.saturating_add(xp),
+            XpChange::Take(xp) => data.xp = data.xp.saturating_sub(xp),
+            XpChange::Set(xp) => data.xp = xp,
+            XpChange::SetMessages(count) => data.msg_count = count,
+            XpChange::Reset => {
+                data.xp = 0;
+                data.msg_count = 0;
+                data.voice_minutes = 0;
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            XpChange::Give(_) => "give",
+            XpChange::Take(_) => "take",
+            XpChange::Set(_) => "set",
+            XpChange::SetMessages(_) => "set messages",
+            XpChange::Reset => "reset",
+        }
+    }
+}
+
+/// A record of who changed a member's totals, from what, to what and why
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct XpAuditEntry {
+    pub staff_id: u64,
+    /// The member whose totals changed, or `None` for a whole-guild reset
+    pub user_id: Option<u64>,
+    pub action: String,
+    pub xp_before: u32,
+    pub xp_after: u32,
+    pub msg_count_before: u32,
+    pub msg_count_after: u32,
+    pub reason: String,
+    pub time: DateTime<Utc>,
+}
+
+impl XpAuditEntry {
+    /// A one-line summary for listing entries in Discord
+    pub fn describe(&self) -> String {
+        let target = match self.user_id {
+            Some(user_id) => format!("<@{}>", user_id),
+            None => String::from("everyone"),
+        };
+        format!(
+            "<@{}> used **{}** on {}: {} → {} XP, {} → {} messages ({})",
+            self.staff_id,
+            self.action,
+            target,
+            self.xp_before,
+            self.xp_after,
+            self.msg_count_before,
+            self.msg_count_after,
+            self.reason
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::storage::{MemoryStorage, Storage};
+
+    fn data(xp: u32, msg_count: u32) -> LevelData {
+        LevelData {
+            msg_count,
+            xp,
+            level: 0,
+            last_msg: Utc.timestamp_opt(0, 0).unwrap(),
+            voice_minutes: 0,
+        }
+    }
+
+    #[test]
+    fn take_stops_at_zero() {
+        let mut d = data(10, 1);
+        XpChange::Take(25).apply(&mut d);
+        assert_eq!(d.xp, 0);
+    }
+
+    #[test]
+    fn reset_clears_everything() {
+        let mut d = data(10, 4);
+        XpChange::Reset.apply(&mut d);
+        assert_eq!((d.xp, d.msg_count), (0, 0));
+    }
+
+    #[test]
+    fn set_messages_keeps_xp() {
+        let mut d = data(10, 4);
+        XpChange::SetMessages(9).apply(&mut d);
+        assert_eq!((d.xp, d.msg_count), (10, 9));
+    }
+
+    #[tokio::test]
+    async fn adjustments_move_period_boards() {
+        let storage = MemoryStorage::new();
+        let periods = vec![String::from("week:2021-W35")];
+        storage
+            .adjust_user_level(1, 2, XpChange::Give(30), &periods)
+            .await
+            .unwrap();
+        let (before, after) = storage
+            .adjust_user_level(1, 2, XpChange::Take(10), &periods)
+            .await
+            .unwrap();
+        assert_eq!((before.xp, after.xp), (30, 20));
+        assert_eq!(
+            storage
+                .get_period_leaderboard_page(1, &periods[0], 0, 10)
+                .await
+                .unwrap(),
+            vec![(2, 20)]
+        );
+
+        storage
+            .adjust_user_level(1, 2, XpChange::Take(50), &periods)
+            .await
+            .unwrap();
+        assert_eq!(storage.get_leaderboard_size(1).await.unwrap(), 0);
+        assert_eq!(
+            storage
+                .get_period_leaderboard_size(1, &periods[0])
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn reset_clears_live_boards_and_keeps_old_seasons() {
+        let storage = MemoryStorage::new();
+        let live = vec![String::from("month:2021-09")];
+        let old = vec![String::from("season:spring")];
+        storage
+            .adjust_user_level(1, 2, XpChange::Give(30), &old)
+            .await
+            .unwrap();
+        storage
+            .adjust_user_level(1, 2, XpChange::Give(30), &live)
+            .await
+            .unwrap();
+        storage
+            .adjust_user_level(1, 3, XpChange::SetMessages(4), &live)
+            .await
+            .unwrap();
+
+        assert_eq!(storage.reset_guild_levels(1, &live).await.unwrap(), 2);
+        assert_eq!(storage.get_user_level(1, 3).await.unwrap().msg_count, 0);
+        assert_eq!(
+            storage
+                .get_period_leaderboard_size(1, &live[0])
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            storage
+                .get_period_leaderboard_page(1, &old[0], 0, 10)
+                .await
+                .unwrap(),
+            vec![(2, 30)]
+        );
+    }
+
+    #[tokio::test]
+    async fn entries_are_newest_first() {
+        let storage = MemoryStorage::new();
+        for xp in 1..=3 {
+            let entry = XpAuditEntry {
+                staff_id: 1,
+                user_id: Some(2),
+                action: XpChange::Set(xp).name().to_string(),
+                xp_before: 0,
+                xp_after: xp,
+                msg_count_before: 0,
+                msg_count_after: 0,
+                reason: String::new(),
+                time: Utc.timestamp_opt(xp as i64, 0).unwrap(),
+            };
+            storage.add_xp_audit_entry(10, &entry).await.unwrap();
+        }
+
+        let entries = storage.get_xp_audit_entries(10, 2).await.unwrap();
+        let xp: Vec<u32> = entries.iter().map(|e| e.xp_after).collect();
+        assert_eq!(xp, vec![3, 2]);
+        assert!(storage
+            .get_xp_audit_entries(11, 2)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+//! Rank card images
+//!
+//! Cards are drawn in-process with `image`, `imageproc` and `rusttype`, using the bundled
+//! DejaVu fonts.
+use image::{imageops, DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_circle_mut, draw_filled_rect_mut, draw_text_mut, text_size};
+use imageproc::rect::Rect;
+use rusttype::{Font, Scale};
+use std::io::Cursor;
+
+const CARD_WIDTH: u32 = 934;
+const CARD_HEIGHT: u32 = 282;
+const AVATAR_SIZE: u32 = 190;
+const BAR_X: i32 = 270;
+const BAR_Y: i32 = 190;
+const BAR_WIDTH: u32 = 620;
+const BAR_HEIGHT: u32 = 36;
+
+pub(crate) const FONT_REGULAR: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans.ttf");
+pub(crate) const FONT_BOLD: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans-Bold.ttf");
+
+/// Colour schemes members can pick for their card
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CardTheme {
+    #[default]
+    Dark,
+    Light,
+    /// WPI crimson and grey
+    Crimson,
+    Ocean,
+}
+
+struct Palette {
+    background: Rgba<u8>,
+    panel: Rgba<u8>,
+    text: Rgba<u8>,
+    muted: Rgba<u8>,
+    bar: Rgba<u8>,
+    accent: Rgba<u8>,
+}
+
+impl CardTheme {
+    pub const ALL: [CardTheme; 4] = [
+        CardTheme::Dark,
+        CardTheme::Light,
+        CardTheme::Crimson,
+        CardTheme::Ocean,
+    ];
+
+    pub fn parse(name: &str) -> Option<CardTheme> {
+        CardTheme::ALL
+            .iter()
+            .find(|theme| theme.name() == name)
+            .copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CardTheme::Dark => "dark",
+            CardTheme::Light => "light",
+            CardTheme::Crimson => "crimson",
+            CardTheme::Ocean => "ocean",
+        }
+    }
+
+    fn palette(&self) -> Palette {
+        match self {
+            CardTheme::Dark => Palette {
+                background: Rgba([35, 39, 42, 255]),
+                panel: Rgba([0, 0, 0, 255]),
+                text: Rgba([255, 255, 255, 255]),
+                muted: Rgba([127, 131, 132, 255]),
+                bar: Rgba([72, 75, 78, 255]),
+                accent: Rgba([98, 211, 245, 255]),
+            },
+            CardTheme::Light => Palette {
+                background: Rgba([240, 242, 245, 255]),
+                panel: Rgba([255, 255, 255, 255]),
+                text: Rgba([32, 34, 37, 255]),
+                muted: Rgba([116, 127, 141, 255]),
+                bar: Rgba([220, 221, 222, 255]),
+                accent: Rgba([88, 101, 242, 255]),
+            },
+            CardTheme::Crimson => Palette {
+                background: Rgba([172, 43, 55, 255]),
+                panel: Rgba([40, 40, 40, 255]),
+                text: Rgba([255, 255, 255, 255]),
+                muted: Rgba([169, 176, 183, 255]),
+                bar: Rgba([85, 85, 85, 255]),
+                accent: Rgba([172, 43, 55, 255]),
+            },
+            CardTheme::Ocean => Palette {
+                background: Rgba([12, 44, 72, 255]),
+                panel: Rgba([8, 26, 44, 255]),
+                text: Rgba([232, 244, 253, 255]),
+                muted: Rgba([122, 160, 190, 255]),
+                bar: Rgba([30, 64, 96, 255]),
+                accent: Rgba([46, 196, 182, 255]),
+            },
+        }
+    }
+}
+
+/// Everything shown on a rank card
+pub struct RankCard<'a> {
+    pub name: &'a str,
+    pub level: u32,
+    /// One-based leaderboard position, if the member has one
+    pub rank: Option<usize>,
+    /// XP earned into the current level
+    pub progress: u64,
+    /// XP the current level takes in total
+    pub level_size: u64,
+    pub avatar: Option<&'a DynamicImage>,
+    pub theme: CardTheme,
+}
+
+/// Draws a bar with rounded ends
+fn draw_pill_mut(image: &mut RgbaImage, x: i32, y: i32, width: u32, height: u32, color: Rgba<u8>) {
+    let radius = (height / 2) as i32;
+    if width <= height {
+        draw_filled_circle_mut(image, (x + radius, y + radius), radius, color);
+        return;
+    }
+
+    draw_filled_rect_mut(
+        image,
+        Rect::at(x + radius, y).of_size(width - height, height),
+        color,
+    );
+    draw_filled_circle_mut(image, (x + radius, y + radius), radius, color);
+    draw_filled_circle_mut(
+        image,
+        (x + width as i32 - radius, y + radius),
+        radius,
+        color,
+    );
+}
+
+/// Crops an avatar into a circle
+fn circle_avatar(avatar: &DynamicImage) -> RgbaImage {
+    let mut avatar = imageops::resize(
+        &avatar.to_rgba8(),
+        AVATAR_SIZE,
+        AVATAR_SIZE,
+        imageops::FilterType::Triangle,
+    );
+    let radius = AVATAR_SIZE as f32 / 2.0;
+    for (x, y, pixel) in avatar.enumerate_pixels_mut() {
+        let dx = x as f32 + 0.5 - radius;
+        let dy = y as f32 + 0.5 - radius;
+        if dx * dx + dy * dy > radius * radius {
+            pixel[3] = 0;
+        }
+    }
+
+    avatar
+}
+
+/// Draws a rank card and encodes it as a PNG
+pub fn render_rank_card(card: &RankCard) -> Result<Vec<u8>, image::ImageError> {
+    let palette = card.theme.palette();
+    let regular = Font::try_from_bytes(FONT_REGULAR).expect("Bundled font is valid");
+    let bold = Font::try_from_bytes(FONT_BOLD).expect("Bundled font is valid");
+
+    let mut image = RgbaImage::from_pixel(CARD_WIDTH, CARD_HEIGHT, palette.background);
+    draw_filled_rect_mut(
+        &mut image,
+        Rect::at(20, 20).of_size(CARD_WIDTH - 40, CARD_HEIGHT - 40),
+        palette.panel,
+    );
+
+    // Avatar
+    let avatar_x = 45;
+    let avatar_y = ((CARD_HEIGHT - AVATAR_SIZE) / 2) as i32;
+    match card.avatar {
+        Some(avatar) => imageops::overlay(
+            &mut image,
+            &circle_avatar(avatar),
+            avatar_x as i64,
+            avatar_y as i64,
+        ),
+        None => {
+            let radius = (AVATAR_SIZE / 2) as i32;
+            draw_filled_circle_mut(
+                &mut image,
+                (avatar_x + radius, avatar_y + radius),
+                radius,
+                palette.bar,
+            );
+        }
+    }
+
+    // Rank and level, right-aligned along the top
+    let big = Scale::uniform(56.0);
+    let small = Scale::uniform(26.0);
+    let rank = card
+        .rank
+        .map(|rank| format!("#{}", rank))
+        .unwrap_or_else(|| String::from("-"));
+    // Drawn right to left, each with the gap to leave before the next
+    let header = vec![
+        (card.level.to_string(), big, &bold, palette.accent, 10),
+        (String::from("LEVEL"), small, &regular, palette.accent, 40),
+        (rank, big, &bold, palette.text, 10),
+        (String::from("RANK"), small, &regular, palette.text, 0),
+    ];
+    let mut right = BAR_X + BAR_WIDTH as i32;
+    for (text, scale, font, color, gap) in header {
+        let (width, _) = text_size(scale, font, &text);
+        right -= width;
+        // Line up the baselines of the big and small text
+        let y = if scale == big { 40 } else { 66 };
+        draw_text_mut(&mut image, color, right, y, scale, font, &text);
+        right -= gap;
+    }
+
+    // Name and XP counter just above the bar
+    let name_scale = Scale::uniform(40.0);
+    draw_text_mut(
+        &mut image,
+        palette.text,
+        BAR_X + 10,
+        BAR_Y - 55,
+        name_scale,
+        &regular,
+        card.name,
+    );
+    let xp_text = format!("{} / {} XP", card.progress, card.level_size);
+    let (xp_width, _) = text_size(small, &regular, &xp_text);
+    draw_text_mut(
+        &mut image,
+        palette.muted,
+        BAR_X + BAR_WIDTH as i32 - xp_width - 10,
+        BAR_Y - 40,
+        small,
+        &regular,
+        &xp_text,
+    );
+
+    // XP bar
+    draw_pill_mut(&mut image, BAR_X, BAR_Y, BAR_WIDTH, BAR_HEIGHT, palette.bar);
+    if card.progress > 0 {
+        let filled = (card.progress.min(card.level_size) * BAR_WIDTH as u64)
+            .checked_div(card.level_size)
+            .map_or(BAR_WIDTH, |filled| filled as u32);
+        draw_pill_mut(
+            &mut image,
+            BAR_X,
+            BAR_Y,
+            filled.max(BAR_HEIGHT),
+            BAR_HEIGHT,
+            palette.accent,
+        );
+    }
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgba8(image).write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+
+    Ok(png)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn theme_names_round_trip() {
+        for theme in CardTheme::ALL.iter() {
+            assert_eq!(CardTheme::parse(theme.name()), Some(*theme));
+        }
+        assert_eq!(CardTheme::parse("plaid"), None);
+    }
+
+    #[test]
+    fn renders_png() {
+        let avatar = DynamicImage::new_rgba8(64, 64);
+        let png = render_rank_card(&RankCard {
+            name: "Gompei",
+            level: 12,
+            rank: Some(3),
+            progress: 400,
+            level_size: 1000,
+            avatar: Some(&avatar),
+            theme: CardTheme::Crimson,
+        })
+        .unwrap();
+
+        let decoded = image::load_from_memory(&png).unwrap();
+        assert_eq!(decoded.width(), CARD_WIDTH);
+        assert_eq!(decoded.height(), CARD_HEIGHT);
+    }
+}
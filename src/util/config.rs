@@ -0,0 +1,99 @@
+//! Per-guild settings
+//!
+//! Settings are free-form strings stored by name through [`Storage::get_setting`] and
+//! [`Storage::set_setting`]; this module gives the typed ones meaning.
+use serenity::model::id::ChannelId;
+
+use crate::util::storage::{Storage, StorageResult};
+
+pub const DEFAULT_LEVEL_UP_TEMPLATE: &str = "GG {user}, you went from level {old} to level {new}!";
+
+/// Where level-up announcements get posted
+#[derive(Clone, Debug, PartialEq)]
+pub enum LevelUpTarget {
+    Off,
+    /// The channel the member leveled up in
+    Same,
+    Channel(ChannelId),
+    Dm,
+}
+
+impl LevelUpTarget {
+    pub fn parse(value: &str) -> Option<LevelUpTarget> {
+        match value {
+            "off" => Some(LevelUpTarget::Off),
+            "same" => Some(LevelUpTarget::Same),
+            "dm" => Some(LevelUpTarget::Dm),
+            _ => value
+                .trim_start_matches("<#")
+                .trim_end_matches('>')
+                .parse::<u64>()
+                .ok()
+                .map(|id| LevelUpTarget::Channel(ChannelId(id))),
+        }
+    }
+
+    pub fn to_setting(&self) -> String {
+        match self {
+            LevelUpTarget::Off => String::from("off"),
+            LevelUpTarget::Same => String::from("same"),
+            LevelUpTarget::Dm => String::from("dm"),
+            LevelUpTarget::Channel(id) => id.0.to_string(),
+        }
+    }
+}
+
+/// Gets where a guild's level-up announcements go, defaulting to the same channel
+pub async fn get_level_up_target(
+    guild_id: u64,
+    storage: &dyn Storage,
+) -> StorageResult<LevelUpTarget> {
+    Ok(storage
+        .get_setting(guild_id, "levelup_target")
+        .await?
+        .and_then(|v| LevelUpTarget::parse(&v))
+        .unwrap_or(LevelUpTarget::Same))
+}
+
+pub async fn get_level_up_template(guild_id: u64, storage: &dyn Storage) -> StorageResult<String> {
+    Ok(storage
+        .get_setting(guild_id, "levelup_template")
+        .await?
+        .unwrap_or_else(|| String::from(DEFAULT_LEVEL_UP_TEMPLATE)))
+}
+
+/// Fills in `{user}`, `{old}` and `{new}` in a level-up template
+pub fn render_level_up(
+    template: &str,
+    user_mention: &str,
+    old_level: u32,
+    new_level: u32,
+) -> String {
+    template
+        .replace("{user}", user_mention)
+        .replace("{old}", &old_level.to_string())
+        .replace("{new}", &new_level.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_targets() {
+        assert_eq!(LevelUpTarget::parse("dm"), Some(LevelUpTarget::Dm));
+        assert_eq!(
+            LevelUpTarget::parse("<#1234>"),
+            Some(LevelUpTarget::Channel(ChannelId(1234)))
+        );
+        assert_eq!(LevelUpTarget::parse("somewhere"), None);
+    }
+
+    #[test]
+    fn render_template() {
+        assert_eq!(
+            render_level_up(DEFAULT_LEVEL_UP_TEMPLATE, "<@1>", 2, 3),
+            "GG <@1>, you went from level 2 to level 3!"
+        );
+    }
+}
@@ -1,8 +1,21 @@
-use log::error;
-use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serenity::client::Context;
+use serenity::prelude::Mutex as AsyncMutex;
 use std::env;
+use std::sync::{Arc, Mutex};
+use tracing::error;
 
-pub fn get_redis_connection() -> Result<redis::Connection, redis::RedisError> {
+use crate::util::abuse::AbuseTracker;
+use crate::util::leveling::LevelingConfig;
+use crate::util::storage::Storage;
+use crate::util::voice::VoiceTracker;
+use crate::{
+    AbuseTrackerContainer, LevelingConfigContainer, ReportLockContainer, StorageContainer,
+    VoiceTrackerContainer,
+};
+
+/// Opens a Redis connection manager, which reconnects on its own if the connection drops
+pub async fn get_redis_connection() -> Result<ConnectionManager, redis::RedisError> {
     let client = match redis::Client::open(
         env::var("REDIS_URL").expect("No Redis URL configured - check your .env file"),
     ) {
@@ -13,12 +26,64 @@ pub fn get_redis_connection() -> Result<redis::Connection, redis::RedisError> {
         }
     };
 
-    match client.get_connection() {
+    match client.get_tokio_connection_manager().await {
         Ok(conn) => Ok(conn),
         Err(e) => {
             error!("Error getting redis connection: {:?}", e);
             panic!("Could not connect to redis");
-            Err(e)
         }
     }
 }
+
+/// Gets the shared storage backend
+pub async fn storage(ctx: &Context) -> Arc<dyn Storage> {
+    ctx.data
+        .read()
+        .await
+        .get::<StorageContainer>()
+        .expect("Storage is inserted at startup")
+        .clone()
+}
+
+/// Gets the leveling settings read at startup
+pub async fn leveling_config(ctx: &Context) -> Arc<LevelingConfig> {
+    ctx.data
+        .read()
+        .await
+        .get::<LevelingConfigContainer>()
+        .expect("Leveling config is inserted at startup")
+        .clone()
+}
+
+/// Gets who is in which voice channel
+pub async fn voice_tracker(ctx: &Context) -> Arc<Mutex<VoiceTracker>> {
+    ctx.data
+        .read()
+        .await
+        .get::<VoiceTrackerContainer>()
+        .expect("Voice tracker is inserted at startup")
+        .clone()
+}
+
+/// Gets everyone's recent messages, as remembered for the anti-abuse rules
+pub async fn abuse_tracker(ctx: &Context) -> Arc<Mutex<AbuseTracker>> {
+    ctx.data
+        .read()
+        .await
+        .get::<AbuseTrackerContainer>()
+        .expect("Abuse tracker is inserted at startup")
+        .clone()
+}
+
+/// Gets the lock held while making or changing a report in a guild
+pub async fn report_lock(ctx: &Context, guild_id: u64) -> Arc<AsyncMutex<()>> {
+    let locks = ctx
+        .data
+        .read()
+        .await
+        .get::<ReportLockContainer>()
+        .expect("Report locks are inserted at startup")
+        .clone();
+    let mut locks = locks.lock().unwrap();
+    locks.entry(guild_id).or_default().clone()
+}
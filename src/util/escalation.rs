@@ -0,0 +1,492 @@
+//! Forwarding reported messages to staff
+//!
+//! Members report a message by reacting to it with one of the guild's escalation emoji, and it's
+//! forwarded to the guild's report channel as a new [`Report`]. Every reporter is recorded in the
+//! report log, whether or not the guild shows them on the forwarded post.
+use chrono::Utc;
+use serenity::{
+    builder::CreateEmbed,
+    client::Context,
+    framework::standard::CommandResult,
+    http::AttachmentType,
+    model::{
+        channel::{Message, Reaction, ReactionType},
+        id::{ChannelId, GuildId},
+    },
+};
+use std::convert::TryFrom;
+use std::env;
+use tracing::error;
+
+use crate::errors::GompeiError;
+use crate::util::data::report_lock;
+use crate::util::reports::{report_buttons, Report, ReportAuditEntry, ReportStatus};
+use crate::util::storage::{Storage, StorageResult};
+
+pub const ESCALATION_CHANNEL_SETTING: &str = "escalation_channel";
+pub const ESCALATION_EMOJI_SETTING: &str = "escalation_emoji";
+pub const ESCALATION_ANONYMOUS_SETTING: &str = "escalation_anonymous";
+
+/// Emoji that report a message in guilds that haven't picked their own
+pub const DEFAULT_ESCALATION_EMOJI: [&str; 4] = ["❗", "‼️", "⁉️", "❕"];
+
+/// Reads an escalation emoji, either a Unicode one or a custom one as `<:name:id>`
+pub fn parse_emoji(value: &str) -> Option<ReactionType> {
+    // Letters and digits on their own are a typo rather than an emoji, and a reaction can never
+    // match them
+    if value.is_empty()
+        || (!value.starts_with('<')
+            && (value.chars().any(char::is_alphabetic)
+                || value.chars().all(|c| c.is_ascii_alphanumeric())))
+    {
+        return None;
+    }
+
+    ReactionType::try_from(value).ok()
+}
+
+/// Whether a reaction is the given escalation emoji
+///
+/// Custom emoji are matched by ID, so renaming one doesn't stop it working. Unicode emoji are
+/// matched with or without the emoji variation selector, which clients don't always send.
+pub fn emoji_matches(emoji: &ReactionType, reaction: &ReactionType) -> bool {
+    match (emoji, reaction) {
+        (ReactionType::Unicode(a), ReactionType::Unicode(b)) => {
+            a.trim_end_matches('\u{fe0f}') == b.trim_end_matches('\u{fe0f}')
+        }
+        (ReactionType::Custom { id: a, .. }, ReactionType::Custom { id: b, .. }) => a == b,
+        _ => false,
+    }
+}
+
+/// Gets the emoji that report a message in a guild
+pub async fn get_escalation_emoji(
+    guild_id: u64,
+    storage: &dyn Storage,
+) -> StorageResult<Vec<ReactionType>> {
+    let emoji = match storage
+        .get_setting(guild_id, ESCALATION_EMOJI_SETTING)
+        .await?
+    {
+        Some(setting) => setting.split_whitespace().filter_map(parse_emoji).collect(),
+        None => DEFAULT_ESCALATION_EMOJI
+            .iter()
+            .filter_map(|e| parse_emoji(e))
+            .collect(),
+    };
+
+    Ok(emoji)
+}
+
+pub async fn set_escalation_emoji(
+    guild_id: u64,
+    emoji: &[ReactionType],
+    storage: &dyn Storage,
+) -> StorageResult<()> {
+    let setting: Vec<String> = emoji.iter().map(|e| e.to_string()).collect();
+    storage
+        .set_setting(guild_id, ESCALATION_EMOJI_SETTING, &setting.join(" "))
+        .await
+}
+
+/// Whether new reports in a guild leave the reporters off the forwarded post
+pub async fn get_anonymous_reports(guild_id: u64, storage: &dyn Storage) -> StorageResult<bool> {
+    Ok(storage
+        .get_setting(guild_id, ESCALATION_ANONYMOUS_SETTING)
+        .await?
+        .as_deref()
+        == Some("on"))
+}
+
+/// Records a member reporting a message in the guild's report log
+async fn log_reporter(
+    guild_id: GuildId,
+    report: &Report,
+    reporter_id: u64,
+    storage: &dyn Storage,
+) -> StorageResult<()> {
+    let entry = ReportAuditEntry {
+        report_id: report.id,
+        reporter_id,
+        channel_id: report.channel_id,
+        message_id: report.message_id,
+        anonymous: report.anonymous,
+        time: Utc::now(),
+    };
+    storage.add_report_audit_entry(guild_id.0, &entry).await
+}
+
+/// Gets the channel a guild's reports are forwarded to, if it has one
+///
+/// Guilds that have never set one fall back to `LOGGING_CHANNEL`, but only if that channel is in
+/// the guild, so one guild's reports never end up in another's.
+pub async fn get_escalation_channel(
+    ctx: &Context,
+    guild_id: GuildId,
+    storage: &dyn Storage,
+) -> StorageResult<Option<ChannelId>> {
+    if let Some(setting) = storage
+        .get_setting(guild_id.0, ESCALATION_CHANNEL_SETTING)
+        .await?
+    {
+        // `off` doesn't parse, and turns forwarding off even with a fallback
+        return Ok(setting.parse::<u64>().ok().map(ChannelId));
+    }
+
+    let fallback = match env::var("LOGGING_CHANNEL").map(|c| c.parse::<u64>()) {
+        Ok(Ok(channel_id)) => ChannelId(channel_id),
+        _ => return Ok(None),
+    };
+    Ok(ctx
+        .cache
+        .guild_channel(fallback)
+        .await
+        .filter(|channel| channel.guild_id == guild_id)
+        .map(|_| fallback))
+}
+
+/// Largest total size of the files re-uploaded with a forwarded message, which is Discord's upload
+/// limit for guilds without boosts
+const FORWARD_UPLOAD_LIMIT: u64 = 8 * 1024 * 1024;
+
+/// Most embeds Discord allows on one message
+const MAX_EMBEDS: usize = 10;
+
+/// Most characters Discord allows in an embed field's value
+const FIELD_LIMIT: usize = 1024;
+
+/// Joins `(name, url)` pairs into markdown links, one per line, leaving out any that would take it
+/// past `limit` characters
+fn link_list(links: &[(String, String)], limit: usize) -> String {
+    let mut list = String::new();
+    for (name, url) in links {
+        let line = format!("[{}]({})", name, url);
+        // Plus one for the newline
+        if list.len() + line.len() + 1 > limit {
+            break;
+        }
+        if !list.is_empty() {
+            list.push('\n');
+        }
+        list.push_str(&line);
+    }
+
+    list
+}
+
+/// Builds the embed describing a reported message
+///
+/// `linked` are the attachments that couldn't be re-uploaded, which are linked to instead.
+fn message_embed(message: &Message, linked: &[(String, String)]) -> CreateEmbed {
+    let mut e = CreateEmbed::default();
+    e.author(|a| {
+        a.name(&message.author.name);
+        a.icon_url(message.author.face());
+        a
+    });
+    if !message.content.is_empty() {
+        e.description(&message.content);
+    }
+    e.field("Link", message.link(), true);
+    if !linked.is_empty() {
+        e.field("Attachments", link_list(linked, FIELD_LIMIT), false);
+    }
+    if !message.stickers.is_empty() {
+        let stickers: Vec<(String, String)> = message
+            .stickers
+            .iter()
+            .map(|sticker| {
+                (
+                    sticker.name.clone(),
+                    format!("https://media.discordapp.net/stickers/{}.png", sticker.id),
+                )
+            })
+            .collect();
+        e.field("Stickers", link_list(&stickers, FIELD_LIMIT), false);
+    }
+
+    e
+}
+
+/// Downloads a message's attachments to upload again, returning the files and the `(name, url)`
+/// of any that were too big or couldn't be downloaded
+async fn download_attachments(
+    message: &Message,
+) -> (Vec<AttachmentType<'static>>, Vec<(String, String)>) {
+    let mut files = Vec::new();
+    let mut linked = Vec::new();
+    let mut uploaded = 0;
+    for attachment in &message.attachments {
+        if uploaded + attachment.size <= FORWARD_UPLOAD_LIMIT {
+            match attachment.download().await {
+                Ok(data) => {
+                    uploaded += attachment.size;
+                    files.push(AttachmentType::Bytes {
+                        data: data.into(),
+                        filename: attachment.filename.clone(),
+                    });
+                    continue;
+                }
+                Err(e) => error!("Could not download {}: {:?}", attachment.url, e),
+            }
+        }
+        linked.push((attachment.filename.clone(), attachment.url.clone()));
+    }
+
+    (files, linked)
+}
+
+/// Adds a reporter to a message's existing report and updates its forwarded post, opening the
+/// report again if it was closed
+///
+/// If the guild is `anonymous` now, the report becomes anonymous too, even if it was made before
+/// that was turned on. It never goes the other way, so reporters who expected to stay hidden are
+/// never shown.
+///
+/// The post is edited before the report is saved, so a failed edit leaves both as they were.
+async fn add_reporter(
+    ctx: &Context,
+    guild_id: GuildId,
+    mut report: Report,
+    reporter_id: u64,
+    anonymous: bool,
+    storage: &dyn Storage,
+) -> CommandResult {
+    let added = !report.reporter_ids.contains(&reporter_id);
+    if added {
+        report.reporter_ids.push(reporter_id);
+    }
+    let reopened = !report.status.is_active();
+    if reopened {
+        report.status = ReportStatus::Open;
+        report.handled_by = None;
+    }
+    let hidden = anonymous && !report.anonymous;
+    if hidden {
+        report.anonymous = true;
+    }
+    if !added && !reopened && !hidden {
+        return Ok(());
+    }
+
+    ChannelId(report.forward_channel_id)
+        .edit_message(&ctx.http, report.forward_message_id, |m| {
+            m.content(report.header());
+            m.components(|c| {
+                report_buttons(c, &report);
+                c
+            })
+        })
+        .await?;
+    storage.set_report(guild_id.0, &report).await?;
+    if added {
+        log_reporter(guild_id, &report, reporter_id, storage).await?;
+    }
+
+    Ok(())
+}
+
+/// Forwards the message a reaction is on to `channel_id` as a new report, and takes the reaction
+/// away
+///
+/// In an anonymous guild the reaction is taken away first, since anyone can see who reacted, and
+/// stays gone even if forwarding fails.
+pub async fn forward_report(
+    ctx: &Context,
+    guild_id: GuildId,
+    reaction: &Reaction,
+    channel_id: ChannelId,
+    storage: &dyn Storage,
+) -> CommandResult {
+    let anonymous = get_anonymous_reports(guild_id.0, storage).await?;
+    if anonymous {
+        reaction.delete(&ctx.http).await?;
+    }
+    make_report(ctx, guild_id, reaction, channel_id, anonymous, storage).await?;
+    if !anonymous {
+        reaction.delete(&ctx.http).await?;
+    }
+
+    Ok(())
+}
+
+/// Forwards a reported message as a new report, or adds the reporter to its existing one
+///
+/// The forwarded copy keeps the message's content, every embed and sticker, and re-uploads its
+/// attachments so they survive the message being deleted. The report only gets its ID once the
+/// copy is posted, so a failed post doesn't leave a gap in the numbers.
+///
+/// If the message has been reported before, the reporter is added to that report and its
+/// forwarded post instead, and a report that was resolved or dismissed is opened again.
+///
+/// The guild's report lock is only held while reading and saving the report, not while the
+/// attachments are copied, so if someone else forwards the message in the meantime this copy is
+/// deleted and the reporter added to theirs.
+async fn make_report(
+    ctx: &Context,
+    guild_id: GuildId,
+    reaction: &Reaction,
+    channel_id: ChannelId,
+    anonymous: bool,
+    storage: &dyn Storage,
+) -> CommandResult {
+    let reporter_id = reaction
+        .user_id
+        .ok_or_else(|| GompeiError::GenericError(String::from("Reaction has no user")))?;
+    let message = reaction
+        .channel_id
+        .message(ctx, reaction.message_id)
+        .await?;
+
+    let lock = report_lock(ctx, guild_id.0).await;
+    {
+        let _guard = lock.lock().await;
+        if let Some(report) = storage.get_message_report(guild_id.0, message.id.0).await? {
+            return add_reporter(ctx, guild_id, report, reporter_id.0, anonymous, storage).await;
+        }
+    }
+
+    let (files, linked) = download_attachments(&message).await;
+    let mut embeds = vec![message_embed(&message, &linked)];
+    embeds.extend(
+        message
+            .embeds
+            .iter()
+            .take(MAX_EMBEDS - 1)
+            .cloned()
+            .map(CreateEmbed::from),
+    );
+    let mut forwarded = channel_id
+        .send_files(&ctx.http, files, |m| m.add_embeds(embeds))
+        .await?;
+
+    let _guard = lock.lock().await;
+    if let Some(report) = storage.get_message_report(guild_id.0, message.id.0).await? {
+        forwarded.delete(ctx).await?;
+        return add_reporter(ctx, guild_id, report, reporter_id.0, anonymous, storage).await;
+    }
+
+    let report = Report {
+        id: storage.next_report_id(guild_id.0).await?,
+        channel_id: reaction.channel_id.0,
+        message_id: message.id.0,
+        author_id: message.author.id.0,
+        reporter_ids: vec![reporter_id.0],
+        forward_channel_id: channel_id.0,
+        forward_message_id: forwarded.id.0,
+        status: ReportStatus::Open,
+        handled_by: None,
+        created: Utc::now(),
+        anonymous,
+    };
+    storage.set_report(guild_id.0, &report).await?;
+    log_reporter(guild_id, &report, reporter_id.0, storage).await?;
+    forwarded
+        .edit(ctx, |m| {
+            m.content(report.header());
+            m.components(|c| {
+                report_buttons(c, &report);
+                c
+            })
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serenity::model::id::EmojiId;
+
+    #[test]
+    fn parses_unicode_and_custom_emoji() {
+        assert_eq!(
+            parse_emoji("❗"),
+            Some(ReactionType::Unicode(String::from("❗")))
+        );
+        assert_eq!(
+            parse_emoji("<:report:1234>"),
+            Some(ReactionType::Custom {
+                animated: false,
+                id: EmojiId(1234),
+                name: Some(String::from("report")),
+            })
+        );
+        assert_eq!(parse_emoji("report"), None);
+    }
+
+    #[test]
+    fn rejects_plain_letters_and_digits() {
+        assert_eq!(parse_emoji("123"), None);
+        assert_eq!(parse_emoji("abc"), None);
+        assert_eq!(parse_emoji("a1"), None);
+        assert!(parse_emoji("1️⃣").is_some());
+    }
+
+    #[test]
+    fn matches_custom_emoji_by_id() {
+        let emoji = parse_emoji("<:report:1234>").unwrap();
+        let renamed = ReactionType::Custom {
+            animated: false,
+            id: EmojiId(1234),
+            name: Some(String::from("flag")),
+        };
+        assert!(emoji_matches(&emoji, &renamed));
+        assert!(!emoji_matches(
+            &emoji,
+            &ReactionType::Unicode(String::from("❗"))
+        ));
+    }
+
+    #[test]
+    fn matches_without_variation_selector() {
+        let emoji = parse_emoji("‼️").unwrap();
+        assert!(emoji_matches(
+            &emoji,
+            &ReactionType::Unicode(String::from("‼"))
+        ));
+    }
+
+    #[test]
+    fn link_list_stops_at_limit() {
+        let links = vec![
+            (String::from("a.png"), String::from("https://x/a.png")),
+            (String::from("b.png"), String::from("https://x/b.png")),
+        ];
+        assert_eq!(
+            link_list(&links, FIELD_LIMIT),
+            "[a.png](https://x/a.png)\n[b.png](https://x/b.png)"
+        );
+        assert_eq!(link_list(&links, 30), "[a.png](https://x/a.png)");
+    }
+
+    #[tokio::test]
+    async fn emoji_round_trip_through_settings() {
+        let storage = crate::util::storage::MemoryStorage::new();
+        assert_eq!(get_escalation_emoji(1, &storage).await.unwrap().len(), 4);
+
+        let emoji = vec![
+            parse_emoji("🚩").unwrap(),
+            parse_emoji("<a:alarm:99>").unwrap(),
+        ];
+        set_escalation_emoji(1, &emoji, &storage).await.unwrap();
+        assert_eq!(get_escalation_emoji(1, &storage).await.unwrap(), emoji);
+    }
+
+    #[tokio::test]
+    async fn reports_are_named_unless_anonymous() {
+        let storage = crate::util::storage::MemoryStorage::new();
+        assert!(!get_anonymous_reports(1, &storage).await.unwrap());
+        storage
+            .set_setting(1, ESCALATION_ANONYMOUS_SETTING, "on")
+            .await
+            .unwrap();
+        assert!(get_anonymous_reports(1, &storage).await.unwrap());
+        storage
+            .set_setting(1, ESCALATION_ANONYMOUS_SETTING, "off")
+            .await
+            .unwrap();
+        assert!(!get_anonymous_reports(1, &storage).await.unwrap());
+    }
+}
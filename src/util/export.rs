@@ -0,0 +1,108 @@
+//! Leaderboard exports for taking standings off Discord
+use serde::Serialize;
+
+use crate::errors::GompeiError;
+
+/// One line of an exported leaderboard
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ExportRow {
+    /// One-based position on the leaderboard
+    pub rank: usize,
+    /// Written as a string, since spreadsheets and JavaScript lose precision on Discord IDs
+    pub user_id: String,
+    pub name: String,
+    pub xp: u32,
+    pub level: u32,
+    pub messages: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Option<ExportFormat> {
+        match name {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// The CSV header, matching [`ExportRow`]'s fields
+const CSV_HEADER: [&str; 6] = ["rank", "user_id", "name", "xp", "level", "messages"];
+
+/// Writes out rows in the given format
+pub fn export_rows(rows: &[ExportRow], format: ExportFormat) -> Result<Vec<u8>, GompeiError> {
+    match format {
+        ExportFormat::Csv => {
+            // Written by hand so an empty export still has one
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(Vec::new());
+            writer
+                .write_record(CSV_HEADER)
+                .map_err(|e| GompeiError::GenericError(e.to_string()))?;
+            for row in rows {
+                writer
+                    .serialize(row)
+                    .map_err(|e| GompeiError::GenericError(e.to_string()))?;
+            }
+            writer
+                .into_inner()
+                .map_err(|e| GompeiError::GenericError(e.to_string()))
+        }
+        ExportFormat::Json => {
+            serde_json::to_vec_pretty(rows).map_err(|e| GompeiError::GenericError(e.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rows() -> Vec<ExportRow> {
+        vec![ExportRow {
+            rank: 1,
+            user_id: String::from("123456789012345678"),
+            name: String::from("Gompei, the Goat"),
+            xp: 500,
+            level: 2,
+            messages: 40,
+        }]
+    }
+
+    #[test]
+    fn csv_has_header_and_quotes_names() {
+        let csv = String::from_utf8(export_rows(&rows(), ExportFormat::Csv).unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "rank,user_id,name,xp,level,messages\n1,123456789012345678,\"Gompei, the Goat\",500,2,40\n"
+        );
+    }
+
+    #[test]
+    fn empty_csv_still_has_header() {
+        let csv = String::from_utf8(export_rows(&[], ExportFormat::Csv).unwrap()).unwrap();
+        assert_eq!(csv, "rank,user_id,name,xp,level,messages\n");
+    }
+
+    #[test]
+    fn json_keeps_ids_as_strings() {
+        let json = export_rows(&rows(), ExportFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value[0]["user_id"], "123456789012345678");
+        assert_eq!(value[0]["xp"], 500);
+    }
+}
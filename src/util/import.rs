@@ -0,0 +1,283 @@
+//! Importing levels exported from other bots
+//!
+//! Exports are JSON or CSV with a user ID, XP and message count for each member, and optionally
+//! the level the other bot gave them. MEE6's `{"players": [...]}` layout is understood as-is.
+use serde_json::Value;
+
+use crate::errors::GompeiError;
+use crate::util::leveling::{get_level_cost, get_level_number, LevelCurve, LevelData};
+
+/// One member's totals from an export
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportRecord {
+    pub user_id: u64,
+    pub xp: u32,
+    pub msg_count: u32,
+    /// The level the other bot had them at, if the export says
+    pub level: Option<u32>,
+}
+
+/// How imported totals become XP here
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportMode {
+    /// Keep everyone's XP and work their level out again with our curve
+    RecomputeLevels,
+    /// Keep everyone's level from the other bot, topping their XP up to what it costs on our curve
+    /// if theirs was cheaper
+    ///
+    /// XP is never lowered, so a member whose XP is worth more here ends up above that level.
+    KeepLevels,
+}
+
+impl ImportRecord {
+    /// The XP a member should have here
+    pub fn xp_for(&self, mode: ImportMode, curve: &LevelCurve) -> u32 {
+        match (mode, self.level) {
+            (ImportMode::KeepLevels, Some(level)) => {
+                let cost = get_level_cost(curve, level as u64).min(u32::MAX as u64) as u32;
+                self.xp.max(cost)
+            }
+            _ => self.xp,
+        }
+    }
+}
+
+/// An export that couldn't be read, or a row in it that was skipped
+fn import_error(message: impl Into<String>) -> GompeiError {
+    GompeiError::CommandError(message.into())
+}
+
+/// Reads a number that might have been written as a string, as Discord IDs often are
+fn json_number(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Gets the first of `names` present on an object
+fn json_field<'a>(object: &'a Value, names: &[&str]) -> Option<&'a Value> {
+    names.iter().find_map(|name| object.get(*name))
+}
+
+const USER_ID_NAMES: [&str; 3] = ["user_id", "id", "userid"];
+const XP_NAMES: [&str; 3] = ["xp", "exp", "experience"];
+const MSG_COUNT_NAMES: [&str; 4] = ["messages", "message_count", "msg_count", "count"];
+const LEVEL_NAMES: [&str; 2] = ["level", "lvl"];
+
+/// Parses a JSON export, returning the records read and a description of each skipped entry
+pub fn parse_json(data: &str) -> Result<(Vec<ImportRecord>, Vec<String>), GompeiError> {
+    let root: Value =
+        serde_json::from_str(data).map_err(|e| import_error(format!("Invalid JSON: {}", e)))?;
+    let entries = match &root {
+        Value::Array(entries) => entries,
+        Value::Object(_) => match root.get("players").or_else(|| root.get("users")) {
+            Some(Value::Array(entries)) => entries,
+            _ => {
+                return Err(import_error(
+                    "Expected a list of members or a `players` list",
+                ))
+            }
+        },
+        _ => {
+            return Err(import_error(
+                "Expected a list of members or a `players` list",
+            ))
+        }
+    };
+
+    let mut records = Vec::new();
+    let mut skipped = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let user_id = json_field(entry, &USER_ID_NAMES).and_then(json_number);
+        let xp = json_field(entry, &XP_NAMES).and_then(json_number);
+        match (user_id, xp) {
+            (Some(user_id), Some(xp)) => records.push(ImportRecord {
+                user_id,
+                xp: xp.min(u32::MAX as u64) as u32,
+                msg_count: json_field(entry, &MSG_COUNT_NAMES)
+                    .and_then(json_number)
+                    .unwrap_or(0)
+                    .min(u32::MAX as u64) as u32,
+                level: json_field(entry, &LEVEL_NAMES)
+                    .and_then(json_number)
+                    .map(|level| level.min(u32::MAX as u64) as u32),
+            }),
+            _ => skipped.push(format!("Entry {}: missing a user ID or XP", i + 1)),
+        }
+    }
+
+    Ok((records, skipped))
+}
+
+/// Parses a CSV export with a header row, returning the records read and a description of each
+/// skipped row
+pub fn parse_csv(data: &str) -> Result<(Vec<ImportRecord>, Vec<String>), GompeiError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| import_error(format!("Invalid CSV: {}", e)))?
+        .iter()
+        .map(|h| h.to_lowercase())
+        .collect();
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.as_str()));
+    let user_id_col = column(&USER_ID_NAMES).ok_or_else(|| import_error("No user ID column"))?;
+    let xp_col = column(&XP_NAMES).ok_or_else(|| import_error("No XP column"))?;
+    let msg_count_col = column(&MSG_COUNT_NAMES);
+    let level_col = column(&LEVEL_NAMES);
+
+    let mut records = Vec::new();
+    let mut skipped = Vec::new();
+    for (i, row) in reader.records().enumerate() {
+        // Row 1 is the header
+        let line = i + 2;
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                skipped.push(format!("Row {}: {}", line, e));
+                continue;
+            }
+        };
+        let number = |col: usize| row.get(col).and_then(|v| v.parse::<u64>().ok());
+        // Optional columns can be blank, but not hold something other than a number
+        let optional = |col: Option<usize>| match col.and_then(|col| row.get(col)) {
+            None | Some("") => Ok(None),
+            Some(value) => value.parse::<u64>().map(Some).map_err(|_| value),
+        };
+        let (user_id, xp) = match (number(user_id_col), number(xp_col)) {
+            (Some(user_id), Some(xp)) => (user_id, xp),
+            _ => {
+                skipped.push(format!("Row {}: missing a user ID or XP", line));
+                continue;
+            }
+        };
+        match (optional(msg_count_col), optional(level_col)) {
+            (Ok(msg_count), Ok(level)) => records.push(ImportRecord {
+                user_id,
+                xp: xp.min(u32::MAX as u64) as u32,
+                msg_count: msg_count.unwrap_or(0).min(u32::MAX as u64) as u32,
+                level: level.map(|level| level.min(u32::MAX as u64) as u32),
+            }),
+            (Err(value), _) | (_, Err(value)) => {
+                skipped.push(format!("Row {}: `{}` isn't a number", line, value))
+            }
+        }
+    }
+
+    Ok((records, skipped))
+}
+
+/// A member's data before and after an import
+pub struct ImportChange {
+    pub user_id: u64,
+    pub before: LevelData,
+    pub after: LevelData,
+}
+
+/// Works out a member's level data after importing `record` over `before`
+///
+/// Only XP and message count come from the import; voice time and the last message time are
+/// kept.
+pub fn apply_record(
+    record: &ImportRecord,
+    before: &LevelData,
+    mode: ImportMode,
+    curve: &LevelCurve,
+) -> LevelData {
+    let mut after = before.clone();
+    after.xp = record.xp_for(mode, curve);
+    after.msg_count = record.msg_count;
+    after.level = get_level_number(curve, after.xp);
+
+    after
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::prelude::*;
+
+    const CURVE: LevelCurve = LevelCurve::Cubic { base: 50 };
+
+    #[test]
+    fn reads_mee6_json() {
+        let (records, skipped) = parse_json(
+            r#"{"players": [
+                {"id": "123456789012345678", "xp": 1500, "message_count": 80, "level": 5},
+                {"username": "nobody"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            records,
+            vec![ImportRecord {
+                user_id: 123456789012345678,
+                xp: 1500,
+                msg_count: 80,
+                level: Some(5),
+            }]
+        );
+        assert_eq!(skipped.len(), 1);
+    }
+
+    #[test]
+    fn reads_csv_with_any_column_order() {
+        let (records, skipped) =
+            parse_csv("messages,XP,user_id\n10, 200, 42\nlots,5,43\n").unwrap();
+        assert_eq!(
+            records,
+            vec![ImportRecord {
+                user_id: 42,
+                xp: 200,
+                msg_count: 10,
+                level: None,
+            }]
+        );
+        assert_eq!(skipped, vec![String::from("Row 3: `lots` isn't a number")]);
+    }
+
+    #[test]
+    fn csv_needs_xp_column() {
+        assert!(parse_csv("user_id,messages\n1,2\n").is_err());
+    }
+
+    #[test]
+    fn keeping_levels_tops_up_xp() {
+        let record = ImportRecord {
+            user_id: 1,
+            xp: 100,
+            msg_count: 3,
+            level: Some(4),
+        };
+        let before = LevelData {
+            msg_count: 0,
+            xp: 0,
+            level: 0,
+            last_msg: Utc.timestamp_opt(0, 0).unwrap(),
+            voice_minutes: 7,
+        };
+
+        let recomputed = apply_record(&record, &before, ImportMode::RecomputeLevels, &CURVE);
+        assert_eq!((recomputed.xp, recomputed.level), (100, 1));
+
+        let kept = apply_record(&record, &before, ImportMode::KeepLevels, &CURVE);
+        assert_eq!(kept.level, 4);
+        assert_eq!(kept.xp as u64, get_level_cost(&CURVE, 4));
+        assert_eq!((kept.msg_count, kept.voice_minutes), (3, 7));
+    }
+
+    #[test]
+    fn keeping_levels_never_lowers_xp() {
+        let xp = get_level_cost(&CURVE, 6) as u32 + 10;
+        let record = ImportRecord {
+            user_id: 1,
+            xp,
+            msg_count: 3,
+            level: Some(4),
+        };
+        assert_eq!(record.xp_for(ImportMode::KeepLevels, &CURVE), xp);
+    }
+}
@@ -1,12 +1,21 @@
 use chrono::prelude::*;
-use serenity::{framework::standard::CommandResult, model::id::UserId, Result};
-use std::sync::Arc;
+use rand::Rng;
+use serenity::{
+    client::Context,
+    framework::standard::CommandResult,
+    model::id::{ChannelId, GuildId, UserId},
+};
+use std::env;
 
-use redis::Commands;
+use crate::util::config::{
+    get_level_up_target, get_level_up_template, render_level_up, LevelUpTarget,
+};
+use crate::util::multipliers::apply_multiplier;
+use crate::util::periods::current_period_ids;
+use crate::util::storage::{Storage, StorageResult};
+use crate::util::streaks::{is_milestone, Streak};
 
-use tracing::{error, info, instrument};
-
-use crate::commands::leveling::LeaderboardData;
+use tracing::{error, instrument};
 
 #[derive(Clone, Debug)]
 pub struct LevelData {
@@ -14,78 +23,402 @@ pub struct LevelData {
     pub xp: u32,
     pub level: u32,
     pub last_msg: chrono::DateTime<Utc>,
+    /// Minutes spent talking in voice channels
+    pub voice_minutes: u32,
+}
+
+/// How much total XP each level costs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LevelCurve {
+    /// `base * level^3`
+    Cubic { base: u64 },
+    /// `base * level^2`
+    Quadratic { base: u64 },
+    /// `base * level`
+    Linear { base: u64 },
+    /// MEE6's curve, where going from `level` to `level + 1` takes `5 * level^2 + 50 * level + 100`
+    Mee6,
 }
 
-fn get_level_number(xp: u32) -> u32 {
-    let xp = xp as f64;
-    let mut level = (xp / 50f64).powf(1f64 / 3f64).floor();
+impl Default for LevelCurve {
+    fn default() -> LevelCurve {
+        LevelCurve::Cubic { base: 50 }
+    }
+}
+
+impl LevelCurve {
+    pub fn parse(name: &str, base: u64) -> Option<LevelCurve> {
+        // A base of zero would make every level free
+        let base = base.max(1);
+        match name {
+            "cubic" => Some(LevelCurve::Cubic { base }),
+            "quadratic" => Some(LevelCurve::Quadratic { base }),
+            "linear" => Some(LevelCurve::Linear { base }),
+            "mee6" => Some(LevelCurve::Mee6),
+            _ => None,
+        }
+    }
+}
+
+/// Leveling settings, read from the environment at startup
+#[derive(Clone, Debug)]
+pub struct LevelingConfig {
+    pub curve: LevelCurve,
+    /// Smallest XP a message can earn
+    pub xp_min: u32,
+    /// Largest XP a message can earn
+    pub xp_max: u32,
+    /// Time a member has to wait between messages that earn XP
+    pub cooldown: chrono::Duration,
+    /// XP earned for each minute in a voice channel with someone else
+    pub voice_xp_per_minute: u32,
+    /// Days a member can go without posting before their streak starts over
+    pub streak_grace_days: i64,
+    /// A streak bonus is given every this many days in a row
+    pub streak_milestone_days: u32,
+    /// XP given at each streak milestone
+    pub streak_bonus_xp: u32,
+}
+
+impl Default for LevelingConfig {
+    fn default() -> LevelingConfig {
+        LevelingConfig {
+            curve: LevelCurve::default(),
+            xp_min: 1,
+            xp_max: 1,
+            cooldown: chrono::Duration::seconds(60),
+            voice_xp_per_minute: 1,
+            streak_grace_days: 0,
+            streak_milestone_days: 7,
+            streak_bonus_xp: 10,
+        }
+    }
+}
+
+impl LevelingConfig {
+    /// Reads `LEVEL_CURVE`, `LEVEL_CURVE_BASE`, `XP_PER_MESSAGE_MIN`, `XP_PER_MESSAGE_MAX`,
+    /// `XP_COOLDOWN_SECS`, `XP_PER_VOICE_MINUTE`, `STREAK_GRACE_DAYS`, `STREAK_MILESTONE_DAYS` and
+    /// `STREAK_BONUS_XP`, falling back to the defaults for anything unset
+    pub fn from_env() -> LevelingConfig {
+        let default = LevelingConfig::default();
+        let base = env::var("LEVEL_CURVE_BASE")
+            .map(|b| b.parse::<u64>().expect("LEVEL_CURVE_BASE must be a number"))
+            .unwrap_or(50);
+        // Going through `parse` even for the default keeps a zero base from making levels free
+        let curve = LevelCurve::parse(
+            &env::var("LEVEL_CURVE").unwrap_or_else(|_| String::from("cubic")),
+            base,
+        )
+        .expect("LEVEL_CURVE must be one of cubic, quadratic, linear or mee6");
+        let xp_min = env::var("XP_PER_MESSAGE_MIN")
+            .map(|x| {
+                x.parse::<u32>()
+                    .expect("XP_PER_MESSAGE_MIN must be a number")
+            })
+            .unwrap_or(default.xp_min);
+        let xp_max = env::var("XP_PER_MESSAGE_MAX")
+            .map(|x| {
+                x.parse::<u32>()
+                    .expect("XP_PER_MESSAGE_MAX must be a number")
+            })
+            .unwrap_or(default.xp_max)
+            .max(xp_min);
+        let cooldown = env::var("XP_COOLDOWN_SECS")
+            .map(|c| c.parse::<i64>().expect("XP_COOLDOWN_SECS must be a number"))
+            .map(chrono::Duration::seconds)
+            .unwrap_or(default.cooldown);
+        let voice_xp_per_minute = env::var("XP_PER_VOICE_MINUTE")
+            .map(|x| {
+                x.parse::<u32>()
+                    .expect("XP_PER_VOICE_MINUTE must be a number")
+            })
+            .unwrap_or(default.voice_xp_per_minute);
+        let streak_grace_days = env::var("STREAK_GRACE_DAYS")
+            .map(|d| {
+                d.parse::<i64>()
+                    .expect("STREAK_GRACE_DAYS must be a number")
+            })
+            .unwrap_or(default.streak_grace_days);
+        let streak_milestone_days = env::var("STREAK_MILESTONE_DAYS")
+            .map(|d| {
+                d.parse::<u32>()
+                    .expect("STREAK_MILESTONE_DAYS must be a number")
+            })
+            .unwrap_or(default.streak_milestone_days);
+        let streak_bonus_xp = env::var("STREAK_BONUS_XP")
+            .map(|x| x.parse::<u32>().expect("STREAK_BONUS_XP must be a number"))
+            .unwrap_or(default.streak_bonus_xp);
+
+        LevelingConfig {
+            curve,
+            xp_min,
+            xp_max,
+            cooldown,
+            voice_xp_per_minute,
+            streak_grace_days,
+            streak_milestone_days,
+            streak_bonus_xp,
+        }
+    }
+
+    /// Picks how much XP a single message earns
+    pub fn roll_message_xp(&self) -> u32 {
+        rand::thread_rng().gen_range(self.xp_min..=self.xp_max)
+    }
+
+    /// Whether a message sent at `now` is too soon after the member's last one to earn XP
+    pub fn on_cooldown(&self, data: &LevelData, now: DateTime<Utc>) -> bool {
+        now - data.last_msg < self.cooldown
+    }
+}
+
+/// Gets the highest level whose cost is covered by `xp`
+///
+/// This is the inverse of [`get_level_cost`].
+pub fn get_level_number(curve: &LevelCurve, xp: u32) -> u32 {
+    let xp = xp as u64;
+
+    // Costs only go up, so search for the last level we can afford
+    let mut low = 0u64;
+    let mut high = 1u64;
+    while get_level_cost(curve, high) <= xp {
+        low = high;
+        high *= 2;
+    }
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if get_level_cost(curve, mid) <= xp {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    low as u32
+}
 
-    if level < 0f64 {
-        level = 0f64;
+/// Gets the total XP needed to reach `level`
+pub fn get_level_cost(curve: &LevelCurve, level: u64) -> u64 {
+    match *curve {
+        LevelCurve::Cubic { base } => level.saturating_pow(3).saturating_mul(base),
+        LevelCurve::Quadratic { base } => level.saturating_pow(2).saturating_mul(base),
+        LevelCurve::Linear { base } => level.saturating_mul(base),
+        LevelCurve::Mee6 => {
+            // Sum of 5i^2 + 50i + 100 for i in 0..level
+            let squares = level
+                .saturating_sub(1)
+                .saturating_mul(level)
+                .saturating_mul(level.saturating_mul(2).saturating_sub(1))
+                / 6;
+            let linear = level.saturating_sub(1).saturating_mul(level) / 2;
+            squares
+                .saturating_mul(5)
+                .saturating_add(linear.saturating_mul(50))
+                .saturating_add(level.saturating_mul(100))
+        }
     }
+}
 
-    level as u32
+/// Gets how far `xp` is through its current level as `(into level, level size)`
+pub fn get_level_progress(curve: &LevelCurve, xp: u32) -> (u64, u64) {
+    let level = get_level_number(curve, xp) as u64;
+    let level_start = get_level_cost(curve, level);
+    let level_end = get_level_cost(curve, level + 1);
+
+    (xp as u64 - level_start, level_end - level_start)
 }
 
-fn get_level_cost(level: u64) -> u64 {
-    (level.pow(3) * 50).into()
+/// Draws a text progress bar `width` characters wide
+pub fn progress_bar(current: u64, total: u64, width: usize) -> String {
+    let filled = (current.min(total) * width as u64)
+        .checked_div(total)
+        .map_or(width, |filled| filled as usize);
+
+    format!("{}{}", "█".repeat(filled), "░".repeat(width - filled))
 }
 
-pub fn get_user_level(user_id: u64, redis_conn: &mut redis::Connection) -> Result<LevelData> {
-    let msg_count = match redis::cmd("GET")
-        .arg(&[format!("{}:count", &user_id)])
-        .query(redis_conn)
+/// Awards XP for a message sent at `now`, scaled by `multiplier`, unless the member is still on
+/// cooldown
+///
+/// A multiplier of zero means the message doesn't count at all. The member's streak is moved on
+/// too, with bonus XP if it reaches a milestone. Returns the member's level data from before and
+/// after the award, or `None` if no XP was given.
+#[instrument(skip(storage, config))]
+pub async fn award_message_xp(
+    storage: &dyn Storage,
+    config: &LevelingConfig,
+    guild_id: u64,
+    user_id: u64,
+    now: DateTime<Utc>,
+    multiplier: f64,
+) -> StorageResult<Option<(LevelData, LevelData)>> {
+    if multiplier <= 0.0 {
+        return Ok(None);
+    }
+    let xp = apply_multiplier(config.roll_message_xp(), multiplier);
+    let periods = current_period_ids(guild_id, now, storage).await?;
+    let (old_data, mut new_data) = match storage
+        .add_message_xp(guild_id, user_id, xp, now, config.cooldown, &periods)
+        .await?
     {
-        Ok(count) => count,
-        _ => 0,
+        Some(awarded) => awarded,
+        None => return Ok(None),
     };
-    let xp: u32 = match redis::cmd("GET")
-        .arg(&[format!("{}:exp", &user_id)])
-        .query(redis_conn)
+    let today = now.date_naive();
+    // The XP is already awarded, so a gap in the history shouldn't stop the level-up
+    if let Err(e) = storage
+        .record_activity(guild_id, user_id, today, xp, 1)
+        .await
     {
-        Ok(xp) => xp,
-        _ => 0,
-    };
-    let last_msg_num: i64 = match redis::cmd("GET")
-        .arg(&[format!("{}:last", &user_id)])
-        .query(redis_conn)
+        error!("Error recording activity: {:?}", e);
+    }
+
+    // Likewise for the streak
+    match advance_streak(
+        storage, config, guild_id, user_id, &old_data, today, &periods,
+    )
+    .await
     {
-        Ok(msg) => msg,
-        _ => 0,
-    };
+        Ok(Some(with_bonus)) => new_data = with_bonus,
+        Ok(None) => {}
+        Err(e) => error!("Error advancing streak: {:?}", e),
+    }
 
-    let last_msg = Utc.timestamp(last_msg_num, 0);
-    let level = get_level_number(xp.into());
+    Ok(Some((old_data, new_data)))
+}
 
-    let level_data = LevelData {
-        msg_count,
-        xp,
-        last_msg,
-        level,
-    };
+/// Moves a member's streak on for a message on `today`, giving the bonus if it reaches a
+/// milestone
+///
+/// Returns the member's level data after the bonus, or `None` if there wasn't one.
+async fn advance_streak(
+    storage: &dyn Storage,
+    config: &LevelingConfig,
+    guild_id: u64,
+    user_id: u64,
+    old_data: &LevelData,
+    today: NaiveDate,
+    periods: &[String],
+) -> StorageResult<Option<LevelData>> {
+    let streak = storage.get_streak(guild_id, user_id).await?;
+    let advanced = streak.advance(
+        old_data.last_msg.date_naive(),
+        today,
+        config.streak_grace_days,
+    );
+    if advanced != streak {
+        storage.set_streak(guild_id, user_id, advanced).await?;
+    }
+    if advanced.current == streak.current
+        || config.streak_bonus_xp == 0
+        || !is_milestone(advanced.current, config.streak_milestone_days)
+    {
+        return Ok(None);
+    }
 
-    Ok(level_data)
+    let bonus = config.streak_bonus_xp;
+    let (_, new_data) = storage
+        .add_bonus_xp(guild_id, user_id, bonus, periods)
+        .await?;
+    if let Err(e) = storage
+        .record_activity(guild_id, user_id, today, bonus, 0)
+        .await
+    {
+        error!("Error recording activity: {:?}", e);
+    }
+
+    Ok(Some(new_data))
 }
 
-#[instrument(skip(redis_conn))]
-pub fn set_user_level(
+/// Gets a member's streak as it stands at `now`, given their level data
+pub async fn get_current_streak(
+    storage: &dyn Storage,
+    config: &LevelingConfig,
+    guild_id: u64,
     user_id: u64,
-    redis_conn: &mut redis::Connection,
-    level_data: LevelData,
-) -> Result<()> {
-    if let Err(e) =
-        redis_conn.set::<String, u32, ()>(format!("{}:count", user_id), level_data.msg_count)
-    {
-        error!("Redis error: {}", e.to_string());
+    level_data: &LevelData,
+    now: DateTime<Utc>,
+) -> StorageResult<Streak> {
+    let streak = storage.get_streak(guild_id, user_id).await?;
+
+    Ok(Streak {
+        current: streak.current_on(
+            level_data.last_msg.date_naive(),
+            now.date_naive(),
+            config.streak_grace_days,
+        ),
+        ..streak
+    })
+}
+
+/// Awards XP for `minutes` spent in voice, scaled by `multiplier`
+///
+/// Returns the member's level data from before and after the award, or `None` if no XP was
+/// given.
+#[instrument(skip(storage, config))]
+pub async fn award_voice_xp(
+    storage: &dyn Storage,
+    config: &LevelingConfig,
+    guild_id: u64,
+    user_id: u64,
+    minutes: u32,
+    now: DateTime<Utc>,
+    multiplier: f64,
+) -> StorageResult<Option<(LevelData, LevelData)>> {
+    if multiplier <= 0.0 {
+        return Ok(None);
     }
-    if let Err(e) = redis_conn.set::<String, u32, ()>(format!("{}:exp", &user_id), level_data.xp) {
-        error!("Redis error: {}", e.to_string());
+    let xp = apply_multiplier(config.voice_xp_per_minute.saturating_mul(minutes), multiplier);
+    let periods = current_period_ids(guild_id, now, storage).await?;
+    let awarded = storage
+        .add_voice_xp(guild_id, user_id, xp, minutes, &periods)
+        .await?;
+    if let Err(e) = storage
+        .record_activity(guild_id, user_id, now.date_naive(), xp, 0)
+        .await
+    {
+        error!("Error recording activity: {:?}", e);
     }
-    if let Err(e) = redis_conn.set::<String, String, ()>(
-        format!("{}:last", &user_id),
-        level_data.last_msg.timestamp().to_string(),
-    ) {
-        error!("Redis error: {}", e.to_string());
+
+    Ok(Some(awarded))
+}
+
+/// Posts a level-up announcement for a member wherever the guild has configured it
+///
+/// `channel_id` is where they leveled up, if it was in a text channel. Without one, announcements
+/// set to go to the same channel are skipped.
+#[instrument(skip(ctx, storage))]
+pub async fn announce_level_up(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    channel_id: Option<ChannelId>,
+    old_level: u32,
+    new_level: u32,
+    storage: &dyn Storage,
+) -> CommandResult {
+    let target = get_level_up_target(guild_id.0, storage).await?;
+    let content = render_level_up(
+        &get_level_up_template(guild_id.0, storage).await?,
+        &format!("<@{}>", user_id.0),
+        old_level,
+        new_level,
+    );
+
+    match target {
+        LevelUpTarget::Off => (),
+        LevelUpTarget::Same => {
+            if let Some(channel_id) = channel_id {
+                channel_id.say(&ctx, content).await?;
+            }
+        }
+        LevelUpTarget::Channel(channel_id) => {
+            channel_id.say(&ctx, content).await?;
+        }
+        LevelUpTarget::Dm => {
+            let pm = user_id.create_dm_channel(&ctx).await?;
+            pm.say(&ctx, content).await?;
+        }
     }
 
     Ok(())
@@ -94,27 +427,249 @@ pub fn set_user_level(
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::util::storage::MemoryStorage;
+    use proptest::prelude::*;
+
+    const CURVE: LevelCurve = LevelCurve::Cubic { base: 50 };
 
     #[test]
     fn zero_xp() {
-        assert_eq!(super::get_level_number(0), 0)
+        assert_eq!(super::get_level_number(&CURVE, 0), 0)
     }
     #[test]
     fn level_3() {
-        assert_eq!(super::get_level_number(2757), 3)
+        assert_eq!(super::get_level_number(&CURVE, 2757), 3)
     }
     #[test]
     fn level_14() {
-        assert_eq!(get_level_number(149818), 14)
+        assert_eq!(get_level_number(&CURVE, 149818), 14)
     }
 
     #[test]
     fn cost_level_3() {
-        assert_eq!(get_level_cost(3), 1350)
+        assert_eq!(get_level_cost(&CURVE, 3), 1350)
     }
 
     #[test]
     fn cost_level_14() {
-        assert_eq!(get_level_cost(14), 137200)
+        assert_eq!(get_level_cost(&CURVE, 14), 137200)
+    }
+
+    #[test]
+    fn progress_through_level() {
+        // Level 3 runs from 1350 to 3200 XP
+        assert_eq!(get_level_progress(&CURVE, 2275), (925, 1850));
+    }
+
+    #[test]
+    fn progress_bars() {
+        assert_eq!(progress_bar(0, 10, 4), "░░░░");
+        assert_eq!(progress_bar(5, 10, 4), "██░░");
+        assert_eq!(progress_bar(10, 10, 4), "████");
+    }
+
+    #[test]
+    fn mee6_costs() {
+        // From MEE6's published level table
+        assert_eq!(get_level_cost(&LevelCurve::Mee6, 1), 100);
+        assert_eq!(get_level_cost(&LevelCurve::Mee6, 2), 255);
+        assert_eq!(get_level_cost(&LevelCurve::Mee6, 10), 4675);
+    }
+
+    #[test]
+    fn zero_base_is_raised_to_one() {
+        env::set_var("LEVEL_CURVE_BASE", "0");
+        let config = LevelingConfig::from_env();
+        env::remove_var("LEVEL_CURVE_BASE");
+
+        assert_eq!(config.curve, LevelCurve::Cubic { base: 1 });
+        assert_eq!(
+            LevelCurve::parse("linear", 0),
+            Some(LevelCurve::Linear { base: 1 })
+        );
+        assert_eq!(get_level_number(&config.curve, 8), 2);
+    }
+
+    fn any_curve() -> impl Strategy<Value = LevelCurve> {
+        prop_oneof![
+            (1u64..1000).prop_map(|base| LevelCurve::Cubic { base }),
+            (1u64..1000).prop_map(|base| LevelCurve::Quadratic { base }),
+            (1u64..1000).prop_map(|base| LevelCurve::Linear { base }),
+            Just(LevelCurve::Mee6),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn level_of_cost_is_level(curve in any_curve(), level in 0u64..500) {
+            let cost = get_level_cost(&curve, level);
+            prop_assume!(cost <= u32::MAX as u64);
+            prop_assert_eq!(get_level_number(&curve, cost as u32) as u64, level);
+        }
+
+        #[test]
+        fn xp_is_between_level_costs(curve in any_curve(), xp in any::<u32>()) {
+            let level = get_level_number(&curve, xp) as u64;
+            prop_assert!(get_level_cost(&curve, level) <= xp as u64);
+            prop_assert!(get_level_cost(&curve, level + 1) > xp as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn first_message_awards_xp() {
+        let storage = MemoryStorage::new();
+        let config = LevelingConfig::default();
+        let (old, new) = award_message_xp(&storage, &config, 1, 2, Utc::now(), 1.0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(old.xp, 0);
+        assert_eq!(new.xp, 1);
+        assert_eq!(new.msg_count, 1);
+        assert_eq!(storage.get_user_level(1, 2).await.unwrap().xp, 1);
+    }
+
+    #[tokio::test]
+    async fn cooldown_blocks_xp() {
+        let storage = MemoryStorage::new();
+        let config = LevelingConfig::default();
+        let now = Utc::now();
+        award_message_xp(&storage, &config, 1, 2, now, 1.0)
+            .await
+            .unwrap();
+
+        let soon = now + chrono::Duration::seconds(30);
+        let data = storage.get_user_level(1, 2).await.unwrap();
+        assert!(config.on_cooldown(&data, soon));
+        assert!(award_message_xp(&storage, &config, 1, 2, soon, 1.0)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(storage.get_user_level(1, 2).await.unwrap().xp, 1);
+
+        let later = now + chrono::Duration::minutes(5);
+        assert!(!config.on_cooldown(&data, later));
+        assert!(award_message_xp(&storage, &config, 1, 2, later, 1.0)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(storage.get_user_level(1, 2).await.unwrap().xp, 2);
+    }
+
+    #[tokio::test]
+    async fn xp_stops_at_the_largest_total() {
+        let storage = MemoryStorage::new();
+        let config = LevelingConfig::default();
+        let mut data = storage.get_user_level(1, 2).await.unwrap();
+        data.xp = u32::MAX;
+        storage.set_user_level(1, 2, data).await.unwrap();
+
+        award_message_xp(&storage, &config, 1, 2, Utc::now(), 1.0)
+            .await
+            .unwrap();
+        assert_eq!(storage.get_user_level(1, 2).await.unwrap().xp, u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn zero_multiplier_skips_message() {
+        let storage = MemoryStorage::new();
+        let config = LevelingConfig::default();
+        assert!(award_message_xp(&storage, &config, 1, 2, Utc::now(), 0.0)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(storage.get_user_level(1, 2).await.unwrap().msg_count, 0);
+    }
+
+    #[tokio::test]
+    async fn streak_milestones_give_bonus() {
+        let storage = MemoryStorage::new();
+        let config = LevelingConfig {
+            streak_milestone_days: 2,
+            streak_bonus_xp: 5,
+            ..LevelingConfig::default()
+        };
+        let day_one = Utc.with_ymd_and_hms(2021, 9, 1, 12, 0, 0).unwrap();
+        award_message_xp(&storage, &config, 1, 2, day_one, 1.0)
+            .await
+            .unwrap();
+
+        let day_two = day_one + chrono::Duration::days(1);
+        let (_, new) = award_message_xp(&storage, &config, 1, 2, day_two, 1.0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(new.xp, 7);
+        assert_eq!(storage.get_user_level(1, 2).await.unwrap().xp, 7);
+
+        let streak = get_current_streak(&storage, &config, 1, 2, &new, day_two)
+            .await
+            .unwrap();
+        assert_eq!(
+            streak,
+            Streak {
+                current: 2,
+                longest: 2
+            }
+        );
+        let lapsed = get_current_streak(
+            &storage,
+            &config,
+            1,
+            2,
+            &new,
+            day_two + chrono::Duration::days(2),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            lapsed,
+            Streak {
+                current: 0,
+                longest: 2
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn xp_is_per_guild() {
+        let storage = MemoryStorage::new();
+        let config = LevelingConfig::default();
+        award_message_xp(&storage, &config, 1, 2, Utc::now(), 1.0)
+            .await
+            .unwrap();
+        assert_eq!(storage.get_user_level(3, 2).await.unwrap().xp, 0);
+    }
+
+    #[tokio::test]
+    async fn leaderboard_is_ordered_by_xp() {
+        let storage = MemoryStorage::new();
+        for (user_id, xp) in &[(10, 5), (11, 50), (12, 20)] {
+            let mut data = storage.get_user_level(1, *user_id).await.unwrap();
+            data.xp = *xp;
+            storage.set_user_level(1, *user_id, data).await.unwrap();
+        }
+
+        assert_eq!(
+            storage.get_leaderboard_page(1, 0, 10).await.unwrap(),
+            vec![(11, 50), (12, 20), (10, 5)]
+        );
+        assert_eq!(
+            storage.get_leaderboard_page(1, 1, 1).await.unwrap(),
+            vec![(12, 20)]
+        );
+        assert_eq!(storage.get_user_rank(1, 10).await.unwrap(), Some(2));
+        assert_eq!(storage.get_user_rank(1, 99).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn members_without_xp_are_not_ranked() {
+        let storage = MemoryStorage::new();
+        let mut data = storage.get_user_level(1, 10).await.unwrap();
+        data.msg_count = 3;
+        storage.set_user_level(1, 10, data).await.unwrap();
+
+        assert_eq!(storage.get_leaderboard_size(1).await.unwrap(), 0);
+        assert_eq!(storage.get_user_rank(1, 10).await.unwrap(), None);
     }
 }
@@ -1,2 +1,17 @@
+pub mod abuse;
+pub mod activity;
+pub mod audit;
+pub mod card;
+pub mod config;
 pub mod data;
+pub mod escalation;
+pub mod export;
+pub mod import;
 pub mod leveling;
+pub mod multipliers;
+pub mod periods;
+pub mod reports;
+pub mod rewards;
+pub mod storage;
+pub mod streaks;
+pub mod voice;
@@ -0,0 +1,188 @@
+//! Per-channel and per-role XP multipliers
+//!
+//! A multiplier of zero turns XP off, which is how no-XP channels, categories and blocking
+//! roles are stored.
+use serenity::{
+    client::Context,
+    model::{
+        channel::Message,
+        id::{ChannelId, RoleId},
+    },
+};
+
+use crate::util::storage::{Storage, StorageResult};
+
+/// What a multiplier applies to. Categories are channels too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum XpTarget {
+    Channel(ChannelId),
+    Role(RoleId),
+}
+
+impl XpTarget {
+    /// The field name used to store the target's multiplier
+    pub fn key(&self) -> String {
+        match self {
+            XpTarget::Channel(id) => format!("channel:{}", id.0),
+            XpTarget::Role(id) => format!("role:{}", id.0),
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<XpTarget> {
+        let (kind, id) = key.split_once(':')?;
+        let id = id.parse::<u64>().ok()?;
+        match kind {
+            "channel" => Some(XpTarget::Channel(ChannelId(id))),
+            "role" => Some(XpTarget::Role(RoleId(id))),
+            _ => None,
+        }
+    }
+
+    /// How the target is shown in Discord
+    pub fn mention(&self) -> String {
+        match self {
+            XpTarget::Channel(id) => format!("<#{}>", id.0),
+            XpTarget::Role(id) => format!("<@&{}>", id.0),
+        }
+    }
+}
+
+/// Works out how much a message's XP is multiplied by
+///
+/// A channel's own multiplier wins over its category's. Any role set to zero blocks XP,
+/// otherwise the member's highest role multiplier is used so roles don't stack. Anything
+/// without a multiplier counts as 1.
+pub fn message_multiplier(
+    multipliers: &[(XpTarget, f64)],
+    channel: ChannelId,
+    category: Option<ChannelId>,
+    roles: &[RoleId],
+) -> f64 {
+    let get = |target: XpTarget| {
+        multipliers
+            .iter()
+            .find(|(t, _)| *t == target)
+            .map(|(_, multiplier)| *multiplier)
+    };
+
+    let channel_multiplier = get(XpTarget::Channel(channel))
+        .or_else(|| category.and_then(|category| get(XpTarget::Channel(category))))
+        .unwrap_or(1.0);
+
+    let role_multipliers: Vec<f64> = roles
+        .iter()
+        .filter_map(|role| get(XpTarget::Role(*role)))
+        .collect();
+    let role_multiplier = if role_multipliers.is_empty() {
+        1.0
+    } else if role_multipliers.contains(&0.0) {
+        0.0
+    } else {
+        role_multipliers.into_iter().fold(f64::MIN, f64::max)
+    };
+
+    channel_multiplier * role_multiplier
+}
+
+/// Works out the multiplier for a guild message from its channel, category and author's roles
+pub async fn get_message_multiplier(
+    ctx: &Context,
+    msg: &Message,
+    storage: &dyn Storage,
+) -> StorageResult<f64> {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(1.0),
+    };
+    let multipliers = storage.get_xp_multipliers(guild_id.0).await?;
+    if multipliers.is_empty() {
+        return Ok(1.0);
+    }
+
+    let category = ctx
+        .cache
+        .guild_channel(msg.channel_id)
+        .await
+        .and_then(|channel| channel.category_id);
+    let roles = msg
+        .member
+        .as_ref()
+        .map(|member| member.roles.clone())
+        .unwrap_or_default();
+
+    Ok(message_multiplier(
+        &multipliers,
+        msg.channel_id,
+        category,
+        &roles,
+    ))
+}
+
+/// Scales rolled XP by a multiplier, rounding to the nearest point
+pub fn apply_multiplier(xp: u32, multiplier: f64) -> u32 {
+    (xp as f64 * multiplier).round() as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CHANNEL: ChannelId = ChannelId(1);
+    const CATEGORY: ChannelId = ChannelId(2);
+
+    #[test]
+    fn keys_round_trip() {
+        for target in &[XpTarget::Channel(ChannelId(5)), XpTarget::Role(RoleId(6))] {
+            assert_eq!(XpTarget::from_key(&target.key()), Some(*target));
+        }
+        assert_eq!(XpTarget::from_key("user:5"), None);
+    }
+
+    #[test]
+    fn channel_overrides_category() {
+        let multipliers = vec![
+            (XpTarget::Channel(CATEGORY), 0.0),
+            (XpTarget::Channel(CHANNEL), 2.0),
+        ];
+        assert_eq!(
+            message_multiplier(&multipliers, CHANNEL, Some(CATEGORY), &[]),
+            2.0
+        );
+        assert_eq!(
+            message_multiplier(&multipliers, ChannelId(3), Some(CATEGORY), &[]),
+            0.0
+        );
+    }
+
+    #[test]
+    fn blocking_role_wins() {
+        let multipliers = vec![
+            (XpTarget::Role(RoleId(1)), 3.0),
+            (XpTarget::Role(RoleId(2)), 0.0),
+        ];
+        assert_eq!(
+            message_multiplier(&multipliers, CHANNEL, None, &[RoleId(1), RoleId(2)]),
+            0.0
+        );
+    }
+
+    #[test]
+    fn roles_take_highest_and_combine_with_channel() {
+        let multipliers = vec![
+            (XpTarget::Channel(CHANNEL), 2.0),
+            (XpTarget::Role(RoleId(1)), 1.5),
+            (XpTarget::Role(RoleId(2)), 1.25),
+        ];
+        assert_eq!(
+            message_multiplier(&multipliers, CHANNEL, None, &[RoleId(1), RoleId(2)]),
+            3.0
+        );
+        assert_eq!(message_multiplier(&[], CHANNEL, None, &[RoleId(1)]), 1.0);
+    }
+
+    #[test]
+    fn multiplied_xp_rounds() {
+        assert_eq!(apply_multiplier(15, 1.5), 23);
+        assert_eq!(apply_multiplier(15, 0.0), 0);
+    }
+}
@@ -0,0 +1,183 @@
+//! Weekly, monthly and seasonal leaderboards
+//!
+//! Alongside the lifetime total, each message's XP is tallied into one board per period it
+//! falls in. Boards are named by a period ID such as `week:2026-W42`, `month:2026-10` or
+//! `season:fall-2026`, so old boards stay around once their period is over.
+use chrono::prelude::*;
+
+use crate::util::storage::{Storage, StorageResult};
+
+/// The guild setting holding the name of the season in progress
+pub const SEASON_SETTING: &str = "season";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Period {
+    Weekly,
+    Monthly,
+    Season,
+}
+
+impl Period {
+    pub fn parse(name: &str) -> Option<Period> {
+        match name {
+            "weekly" | "week" => Some(Period::Weekly),
+            "monthly" | "month" => Some(Period::Monthly),
+            "season" => Some(Period::Season),
+            _ => None,
+        }
+    }
+
+    /// How the period is described in leaderboard titles
+    pub fn title(&self) -> &'static str {
+        match self {
+            Period::Weekly => "This week",
+            Period::Monthly => "This month",
+            Period::Season => "This season",
+        }
+    }
+}
+
+/// The ID of the board a message sent at `now` counts towards
+///
+/// Weeks are ISO weeks, so they start on Monday. Seasons need a name, and there's no board
+/// without one.
+pub fn period_id(period: Period, now: DateTime<Utc>, season: Option<&str>) -> Option<String> {
+    match period {
+        Period::Weekly => {
+            let week = now.iso_week();
+            Some(format!("week:{}-W{:02}", week.year(), week.week()))
+        }
+        Period::Monthly => Some(format!("month:{}", now.format("%Y-%m"))),
+        Period::Season => season.map(season_id),
+    }
+}
+
+/// The ID of a named season's board
+pub fn season_id(name: &str) -> String {
+    format!("season:{}", name)
+}
+
+/// Whether `name` can be used for a season
+///
+/// `levels season 2` reads a number as a page, so a season named only with digits could never be
+/// looked up.
+pub fn is_valid_season_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Gets the name of the guild's season in progress, if there is one
+pub async fn get_current_season(
+    guild_id: u64,
+    storage: &dyn Storage,
+) -> StorageResult<Option<String>> {
+    storage.get_setting(guild_id, SEASON_SETTING).await
+}
+
+/// Gets the IDs of every board a message sent at `now` should count towards
+pub async fn current_period_ids(
+    guild_id: u64,
+    now: DateTime<Utc>,
+    storage: &dyn Storage,
+) -> StorageResult<Vec<String>> {
+    let season = get_current_season(guild_id, storage).await?;
+
+    Ok([Period::Weekly, Period::Monthly, Period::Season]
+        .iter()
+        .filter_map(|period| period_id(*period, now, season.as_deref()))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::leveling::{award_message_xp, LevelingConfig};
+    use crate::util::storage::MemoryStorage;
+
+    #[test]
+    fn season_names_need_more_than_digits() {
+        assert!(is_valid_season_name("fall-2026"));
+        assert!(is_valid_season_name("2026b"));
+        assert!(!is_valid_season_name("2024"));
+        assert!(!is_valid_season_name(""));
+    }
+
+    #[test]
+    fn weeks_are_iso_weeks() {
+        // A Sunday, which still belongs to the week that started on the Monday before
+        let sunday = Utc.with_ymd_and_hms(2021, 1, 3, 12, 0, 0).unwrap();
+        assert_eq!(
+            period_id(Period::Weekly, sunday, None).unwrap(),
+            "week:2020-W53"
+        );
+        let monday = Utc.with_ymd_and_hms(2021, 1, 4, 0, 0, 0).unwrap();
+        assert_eq!(
+            period_id(Period::Weekly, monday, None).unwrap(),
+            "week:2021-W01"
+        );
+    }
+
+    #[test]
+    fn months_are_zero_padded() {
+        let now = Utc.with_ymd_and_hms(2021, 3, 31, 23, 59, 59).unwrap();
+        assert_eq!(
+            period_id(Period::Monthly, now, None).unwrap(),
+            "month:2021-03"
+        );
+    }
+
+    #[test]
+    fn no_season_without_a_name() {
+        let now = Utc::now();
+        assert_eq!(period_id(Period::Season, now, None), None);
+        assert_eq!(
+            period_id(Period::Season, now, Some("fall-2021")).unwrap(),
+            "season:fall-2021"
+        );
+    }
+
+    #[tokio::test]
+    async fn messages_count_towards_current_periods() {
+        let storage = MemoryStorage::new();
+        let config = LevelingConfig::default();
+        let now = Utc.with_ymd_and_hms(2021, 9, 1, 12, 0, 0).unwrap();
+        award_message_xp(&storage, &config, 1, 2, now, 1.0)
+            .await
+            .unwrap();
+        storage
+            .set_setting(1, SEASON_SETTING, "fall-2021")
+            .await
+            .unwrap();
+        let later = now + chrono::Duration::minutes(5);
+        award_message_xp(&storage, &config, 1, 2, later, 1.0)
+            .await
+            .unwrap();
+
+        let month = period_id(Period::Monthly, now, None).unwrap();
+        assert_eq!(
+            storage
+                .get_period_leaderboard_page(1, &month, 0, 10)
+                .await
+                .unwrap(),
+            vec![(2, 2)]
+        );
+        assert_eq!(
+            storage
+                .get_period_leaderboard_page(1, &season_id("fall-2021"), 0, 10)
+                .await
+                .unwrap(),
+            vec![(2, 1)]
+        );
+        let next_month = period_id(
+            Period::Monthly,
+            Utc.with_ymd_and_hms(2021, 10, 1, 0, 0, 0).unwrap(),
+            None,
+        );
+        assert_eq!(
+            storage
+                .get_period_leaderboard_size(1, &next_month.unwrap())
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
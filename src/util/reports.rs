@@ -0,0 +1,418 @@
+//! Tracked reports of escalated messages
+//!
+//! Each forwarded message is a report with an ID and a status. Staff change the status with the
+//! buttons on the forwarded post, which is edited to show who handled it. Reporting a message
+//! again adds the reporter to its existing report rather than forwarding it twice, and opens the
+//! report again if it was closed.
+//!
+//! Guilds can make reports anonymous, which leaves the reporters off the forwarded post. Who
+//! reported what is always kept in the report log, which only server managers can read.
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    builder::CreateComponents,
+    client::Context,
+    framework::standard::CommandResult,
+    model::interactions::{
+        message_component::{ButtonStyle, MessageComponentInteraction},
+        InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
+    },
+};
+
+use crate::util::data::{report_lock, storage};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportStatus {
+    Open,
+    Claimed,
+    Resolved,
+    Dismissed,
+}
+
+impl ReportStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReportStatus::Open => "Open",
+            ReportStatus::Claimed => "Claimed",
+            ReportStatus::Resolved => "Resolved",
+            ReportStatus::Dismissed => "Dismissed",
+        }
+    }
+
+    /// Whether the report still needs looking at
+    pub fn is_active(&self) -> bool {
+        matches!(self, ReportStatus::Open | ReportStatus::Claimed)
+    }
+}
+
+/// A reported message and what staff have done about it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    /// Counts up from 1 in each guild
+    pub id: u64,
+    /// Where the reported message was posted
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub author_id: u64,
+    /// Everyone who reported the message, first reporter first
+    pub reporter_ids: Vec<u64>,
+    /// The forwarded copy in the report channel
+    pub forward_channel_id: u64,
+    pub forward_message_id: u64,
+    pub status: ReportStatus,
+    /// The staff member who last changed the status
+    pub handled_by: Option<u64>,
+    pub created: DateTime<Utc>,
+    /// Whether the reporters are left off the forwarded post
+    #[serde(default)]
+    pub anonymous: bool,
+}
+
+impl Report {
+    /// The status line, as shown on the forwarded post and in the report list
+    pub fn status_line(&self) -> String {
+        match self.handled_by {
+            Some(staff_id) if self.status != ReportStatus::Open => {
+                format!("{} by <@{}>", self.status.name(), staff_id)
+            }
+            _ => String::from(self.status.name()),
+        }
+    }
+
+    /// The text above the forwarded copy of the message
+    pub fn header(&self) -> String {
+        let count = match self.reporter_ids.len() {
+            0 | 1 => String::new(),
+            n => format!(" · {} reporters", n),
+        };
+        if self.anonymous {
+            return format!(
+                "**Report #{}** · {}{}\nMessage reported anonymously from <#{}>",
+                self.id,
+                self.status_line(),
+                count,
+                self.channel_id
+            );
+        }
+
+        let mut reporters: Vec<String> = self
+            .reporter_ids
+            .iter()
+            .take(LISTED_REPORTERS)
+            .map(|id| format!("<@{}>", id))
+            .collect();
+        if self.reporter_ids.len() > LISTED_REPORTERS {
+            reporters.push(format!(
+                "{} more",
+                self.reporter_ids.len() - LISTED_REPORTERS
+            ));
+        }
+
+        format!(
+            "**Report #{}** · {}{}\nMessage forwarded by {} from <#{}>",
+            self.id,
+            self.status_line(),
+            count,
+            reporters.join(", "),
+            self.channel_id
+        )
+    }
+
+    pub fn forward_link(&self, guild_id: u64) -> String {
+        format!(
+            "https://discord.com/channels/{}/{}/{}",
+            guild_id, self.forward_channel_id, self.forward_message_id
+        )
+    }
+}
+
+/// A record of one member reporting a message, kept even when the report is anonymous
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReportAuditEntry {
+    pub report_id: u64,
+    pub reporter_id: u64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub anonymous: bool,
+    pub time: DateTime<Utc>,
+}
+
+impl ReportAuditEntry {
+    /// A one-line summary for listing entries in Discord
+    pub fn describe(&self) -> String {
+        format!(
+            "<@{}> reported a message in <#{}> (report #{}{})",
+            self.reporter_id,
+            self.channel_id,
+            self.report_id,
+            if self.anonymous { ", anonymous" } else { "" }
+        )
+    }
+}
+
+/// Reporters named on a forwarded post before the rest are just counted, to stay well under the
+/// message length limit
+const LISTED_REPORTERS: usize = 10;
+
+/// Button IDs look like `report:<id>:<action>`
+const BUTTON_PREFIX: &str = "report";
+
+/// Reads a report button's ID into the report and the status it moves it to
+pub fn parse_report_button(custom_id: &str) -> Option<(u64, ReportStatus)> {
+    let parts: Vec<&str> = custom_id.split(':').collect();
+    match parts.as_slice() {
+        [BUTTON_PREFIX, id, action] => {
+            let status = match *action {
+                "claim" => ReportStatus::Claimed,
+                "resolve" => ReportStatus::Resolved,
+                "dismiss" => ReportStatus::Dismissed,
+                "reopen" => ReportStatus::Open,
+                _ => return None,
+            };
+            Some((id.parse().ok()?, status))
+        }
+        _ => None,
+    }
+}
+
+/// Adds the buttons for whatever can be done with a report next
+pub fn report_buttons(c: &mut CreateComponents, report: &Report) {
+    let buttons: &[(&str, &str, ButtonStyle)] = match report.status {
+        ReportStatus::Open => &[
+            ("claim", "Claim", ButtonStyle::Primary),
+            ("resolve", "Resolve", ButtonStyle::Success),
+            ("dismiss", "Dismiss", ButtonStyle::Secondary),
+        ],
+        ReportStatus::Claimed => &[
+            ("resolve", "Resolve", ButtonStyle::Success),
+            ("dismiss", "Dismiss", ButtonStyle::Secondary),
+            ("reopen", "Unclaim", ButtonStyle::Secondary),
+        ],
+        ReportStatus::Resolved | ReportStatus::Dismissed => {
+            &[("reopen", "Reopen", ButtonStyle::Secondary)]
+        }
+    };
+    c.create_action_row(|r| {
+        for (action, label, style) in buttons {
+            r.create_button(|b| {
+                b.custom_id(format!("{}:{}:{}", BUTTON_PREFIX, report.id, action))
+                    .label(label)
+                    .style(*style)
+            });
+        }
+        r
+    });
+}
+
+/// Changes a report's status when one of its buttons is pressed
+///
+/// The report channel can be any channel, so the member needs the same Manage Messages permission
+/// as `reports`. Presses on other components, or on posts that aren't the report's, are left
+/// alone.
+pub async fn handle_report_button(
+    ctx: &Context,
+    interaction: &MessageComponentInteraction,
+) -> CommandResult {
+    let (id, status) = match parse_report_button(&interaction.data.custom_id) {
+        Some(button) => button,
+        None => return Ok(()),
+    };
+    let guild_id = match interaction.guild_id {
+        Some(guild_id) => guild_id.0,
+        None => return Ok(()),
+    };
+
+    let allowed = matches!(
+        interaction.member.as_ref().and_then(|m| m.permissions),
+        Some(permissions) if permissions.manage_messages()
+    );
+    if !allowed {
+        interaction
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| {
+                        d.content("You need the Manage Messages permission to handle reports")
+                            .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                    })
+            })
+            .await?;
+        return Ok(());
+    }
+
+    let storage = storage(ctx).await;
+    let lock = report_lock(ctx, guild_id).await;
+    let _guard = lock.lock().await;
+    let mut report = match storage.get_report(guild_id, id).await? {
+        Some(report) if report.forward_message_id == interaction.message.id.0 => report,
+        _ => return Ok(()),
+    };
+    report.status = status;
+    report.handled_by = if status == ReportStatus::Open {
+        None
+    } else {
+        Some(interaction.user.id.0)
+    };
+    storage.set_report(guild_id, &report).await?;
+
+    interaction
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| {
+                    d.content(report.header());
+                    d.components(|c| {
+                        report_buttons(c, &report);
+                        c
+                    })
+                })
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::storage::{MemoryStorage, Storage};
+
+    fn report() -> Report {
+        Report {
+            id: 3,
+            channel_id: 10,
+            message_id: 11,
+            author_id: 12,
+            reporter_ids: vec![13],
+            forward_channel_id: 20,
+            forward_message_id: 21,
+            status: ReportStatus::Open,
+            handled_by: None,
+            created: Utc.timestamp_opt(0, 0).unwrap(),
+            anonymous: false,
+        }
+    }
+
+    #[test]
+    fn parses_buttons() {
+        assert_eq!(
+            parse_report_button("report:3:claim"),
+            Some((3, ReportStatus::Claimed))
+        );
+        assert_eq!(
+            parse_report_button("report:3:reopen"),
+            Some((3, ReportStatus::Open))
+        );
+        assert_eq!(parse_report_button("report:x:claim"), None);
+        assert_eq!(parse_report_button("next"), None);
+    }
+
+    #[test]
+    fn header_shows_handler() {
+        let mut report = report();
+        assert_eq!(
+            report.header(),
+            "**Report #3** · Open\nMessage forwarded by <@13> from <#10>"
+        );
+        report.status = ReportStatus::Resolved;
+        report.handled_by = Some(99);
+        assert_eq!(report.status_line(), "Resolved by <@99>");
+    }
+
+    #[test]
+    fn header_counts_reporters() {
+        let mut report = report();
+        report.reporter_ids = (1..=12).collect();
+        assert_eq!(
+            report.header(),
+            "**Report #3** · Open · 12 reporters\nMessage forwarded by <@1>, <@2>, <@3>, <@4>, \
+             <@5>, <@6>, <@7>, <@8>, <@9>, <@10>, 2 more from <#10>"
+        );
+    }
+
+    #[test]
+    fn anonymous_header_hides_reporters() {
+        let mut report = report();
+        report.anonymous = true;
+        report.reporter_ids = vec![13, 14];
+        assert_eq!(
+            report.header(),
+            "**Report #3** · Open · 2 reporters\nMessage reported anonymously from <#10>"
+        );
+    }
+
+    #[test]
+    fn reports_from_before_anonymity_still_load() {
+        let json = serde_json::to_string(&report()).unwrap();
+        let old = json.replace(r#","anonymous":false"#, "");
+        assert_ne!(old, json);
+        assert_eq!(serde_json::from_str::<Report>(&old).unwrap(), report());
+    }
+
+    #[test]
+    fn status_round_trips_as_lowercase() {
+        let json = serde_json::to_string(&report()).unwrap();
+        assert!(json.contains(r#""status":"open""#));
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report());
+    }
+
+    #[tokio::test]
+    async fn only_active_reports_are_listed() {
+        let storage = MemoryStorage::new();
+        let mut first = report();
+        first.id = storage.next_report_id(1).await.unwrap();
+        let mut second = report();
+        second.id = storage.next_report_id(1).await.unwrap();
+        second.message_id = 12;
+        assert_eq!((first.id, second.id), (1, 2));
+        storage.set_report(1, &first).await.unwrap();
+        storage.set_report(1, &second).await.unwrap();
+
+        first.status = ReportStatus::Dismissed;
+        storage.set_report(1, &first).await.unwrap();
+        let active: Vec<u64> = storage
+            .get_active_reports(1)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(active, vec![2]);
+        assert_eq!(
+            storage.get_report(1, 1).await.unwrap().unwrap().status,
+            ReportStatus::Dismissed
+        );
+        assert_eq!(
+            storage
+                .get_message_report(1, 12)
+                .await
+                .unwrap()
+                .map(|r| r.id),
+            Some(2)
+        );
+        assert_eq!(storage.get_message_report(1, 13).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn report_log_is_newest_first() {
+        let storage = MemoryStorage::new();
+        for reporter_id in 1..=3 {
+            let entry = ReportAuditEntry {
+                report_id: 3,
+                reporter_id,
+                channel_id: 10,
+                message_id: 11,
+                anonymous: true,
+                time: Utc.timestamp_opt(reporter_id as i64, 0).unwrap(),
+            };
+            storage.add_report_audit_entry(1, &entry).await.unwrap();
+        }
+
+        let entries = storage.get_report_audit_entries(1, 2).await.unwrap();
+        let reporters: Vec<u64> = entries.iter().map(|e| e.reporter_id).collect();
+        assert_eq!(reporters, vec![3, 2]);
+        assert_eq!(
+            entries[0].describe(),
+            "<@3> reported a message in <#10> (report #3, anonymous)"
+        );
+    }
+}
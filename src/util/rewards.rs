@@ -0,0 +1,115 @@
+//! Roles granted for reaching a level
+//!
+//! Each guild maps levels to roles through [`Storage::get_rewards`].
+use serenity::{
+    client::Context,
+    framework::standard::CommandResult,
+    model::id::{GuildId, RoleId},
+};
+use tracing::instrument;
+
+use crate::util::storage::{Storage, StorageResult};
+
+/// Whether lower-tier reward roles are kept when a member reaches a higher one
+pub async fn get_keep_lower_rewards(guild_id: u64, storage: &dyn Storage) -> StorageResult<bool> {
+    Ok(storage
+        .get_setting(guild_id, "reward_mode")
+        .await?
+        .as_deref()
+        != Some("replace"))
+}
+
+/// Works out which reward roles a member at `level` should and shouldn't have
+///
+/// Returns `(wanted, unwanted)`. When `keep_lower` is off only the highest reward
+/// reached is wanted.
+pub fn reward_roles_for_level(
+    rewards: &[(u32, RoleId)],
+    level: u32,
+    keep_lower: bool,
+) -> (Vec<RoleId>, Vec<RoleId>) {
+    let reached: Vec<RoleId> = rewards
+        .iter()
+        .filter(|(reward_level, _)| *reward_level <= level)
+        .map(|(_, role)| *role)
+        .collect();
+
+    let wanted = if keep_lower {
+        reached
+    } else {
+        reached.last().cloned().into_iter().collect()
+    };
+    let unwanted = rewards
+        .iter()
+        .map(|(_, role)| *role)
+        .filter(|role| !wanted.contains(role))
+        .collect();
+
+    (wanted, unwanted)
+}
+
+/// Adds and removes a member's reward roles to match their level
+#[instrument(skip(ctx, storage))]
+pub async fn sync_member_rewards(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: u64,
+    level: u32,
+    storage: &dyn Storage,
+) -> CommandResult {
+    let rewards = storage.get_rewards(guild_id.0).await?;
+    if rewards.is_empty() {
+        return Ok(());
+    }
+    let keep_lower = get_keep_lower_rewards(guild_id.0, storage).await?;
+    let (wanted, unwanted) = reward_roles_for_level(&rewards, level, keep_lower);
+
+    let mut member = guild_id.member(ctx, user_id).await?;
+    let to_add: Vec<RoleId> = wanted
+        .into_iter()
+        .filter(|role| !member.roles.contains(role))
+        .collect();
+    let to_remove: Vec<RoleId> = unwanted
+        .into_iter()
+        .filter(|role| member.roles.contains(role))
+        .collect();
+
+    if !to_add.is_empty() {
+        member.add_roles(&ctx.http, &to_add).await?;
+    }
+    if !to_remove.is_empty() {
+        member.remove_roles(&ctx.http, &to_remove).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rewards() -> Vec<(u32, RoleId)> {
+        vec![(5, RoleId(1)), (10, RoleId(2)), (20, RoleId(3))]
+    }
+
+    #[test]
+    fn below_first_reward() {
+        let (wanted, unwanted) = reward_roles_for_level(&rewards(), 4, true);
+        assert!(wanted.is_empty());
+        assert_eq!(unwanted.len(), 3);
+    }
+
+    #[test]
+    fn keeps_lower_rewards() {
+        let (wanted, unwanted) = reward_roles_for_level(&rewards(), 12, true);
+        assert_eq!(wanted, vec![RoleId(1), RoleId(2)]);
+        assert_eq!(unwanted, vec![RoleId(3)]);
+    }
+
+    #[test]
+    fn replaces_lower_rewards() {
+        let (wanted, unwanted) = reward_roles_for_level(&rewards(), 12, false);
+        assert_eq!(wanted, vec![RoleId(2)]);
+        assert_eq!(unwanted, vec![RoleId(1), RoleId(3)]);
+    }
+}
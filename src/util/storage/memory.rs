@@ -0,0 +1,588 @@
+use chrono::{prelude::*, Duration};
+use serenity::{async_trait, model::id::RoleId};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use super::{Storage, StorageResult};
+use crate::util::abuse::AbuseEntry;
+use crate::util::audit::{XpAuditEntry, XpChange};
+use crate::util::leveling::{get_level_number, LevelCurve, LevelData};
+use crate::util::multipliers::XpTarget;
+use crate::util::reports::{Report, ReportAuditEntry};
+use crate::util::streaks::Streak;
+
+fn empty_level_data() -> LevelData {
+    LevelData {
+        msg_count: 0,
+        xp: 0,
+        level: 0,
+        last_msg: Utc.timestamp_opt(0, 0).unwrap(),
+        voice_minutes: 0,
+    }
+}
+
+#[derive(Default)]
+struct MemoryData {
+    levels: HashMap<(u64, u64), LevelData>,
+    streaks: HashMap<(u64, u64), Streak>,
+    /// XP per member for each `(guild, period)` board
+    periods: HashMap<(u64, String), HashMap<u64, u32>>,
+    settings: HashMap<(u64, String), String>,
+    user_settings: HashMap<(u64, String), String>,
+    /// Newest first, like the Redis list
+    xp_audit: HashMap<u64, Vec<XpAuditEntry>>,
+    /// `(xp, messages)` by guild, member (or `None` for the whole guild) and day
+    activity: HashMap<(u64, Option<u64>, NaiveDate), (u32, u32)>,
+    /// Newest first, like the Redis list
+    abuse: HashMap<(u64, u64), Vec<AbuseEntry>>,
+    /// The last report ID handed out in each guild
+    report_ids: HashMap<u64, u64>,
+    reports: BTreeMap<(u64, u64), Report>,
+    /// Report IDs by guild and reported message
+    reported_messages: HashMap<(u64, u64), u64>,
+    /// Newest first, like the Redis list
+    report_audit: HashMap<u64, Vec<ReportAuditEntry>>,
+    rewards: HashMap<u64, BTreeMap<u32, RoleId>>,
+    xp_multipliers: HashMap<u64, HashMap<XpTarget, f64>>,
+}
+
+/// Sorts `(user_id, xp)` pairs in the same order as a Redis ZREVRANGE
+fn sort_leaderboard(leaderboard: &mut [(u64, u32)]) {
+    leaderboard.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+}
+
+impl MemoryData {
+    /// A guild's `(user_id, xp)` pairs in the same order as a Redis ZREVRANGE
+    ///
+    /// Members without XP aren't in the Redis sorted set, so they're left out here too.
+    fn leaderboard(&self, guild_id: u64) -> Vec<(u64, u32)> {
+        let mut leaderboard: Vec<(u64, u32)> = self
+            .levels
+            .iter()
+            .filter(|((guild, _), data)| *guild == guild_id && data.xp > 0)
+            .map(|((_, user_id), data)| (*user_id, data.xp))
+            .collect();
+        sort_leaderboard(&mut leaderboard);
+
+        leaderboard
+    }
+
+    fn add_period_xp(&mut self, guild_id: u64, user_id: u64, xp: u32, periods: &[String]) {
+        for period in periods {
+            *self
+                .periods
+                .entry((guild_id, period.clone()))
+                .or_default()
+                .entry(user_id)
+                .or_default() += xp;
+        }
+    }
+
+    fn period_leaderboard(&self, guild_id: u64, period: &str) -> Vec<(u64, u32)> {
+        let mut leaderboard: Vec<(u64, u32)> = self
+            .periods
+            .get(&(guild_id, period.to_string()))
+            .map(|tally| tally.iter().map(|(user_id, xp)| (*user_id, *xp)).collect())
+            .unwrap_or_default();
+        sort_leaderboard(&mut leaderboard);
+
+        leaderboard
+    }
+}
+
+/// Storage that lives only as long as the process, for tests
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: Mutex<MemoryData>,
+    curve: LevelCurve,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    pub fn with_curve(curve: LevelCurve) -> MemoryStorage {
+        MemoryStorage {
+            data: Mutex::default(),
+            curve,
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get_user_level(&self, guild_id: u64, user_id: u64) -> StorageResult<LevelData> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .levels
+            .get(&(guild_id, user_id))
+            .cloned()
+            .unwrap_or_else(empty_level_data))
+    }
+
+    async fn get_user_levels(
+        &self,
+        guild_id: u64,
+        user_ids: &[u64],
+    ) -> StorageResult<Vec<LevelData>> {
+        let data = self.data.lock().unwrap();
+        Ok(user_ids
+            .iter()
+            .map(|user_id| {
+                data.levels
+                    .get(&(guild_id, *user_id))
+                    .cloned()
+                    .unwrap_or_else(empty_level_data)
+            })
+            .collect())
+    }
+
+    async fn set_user_level(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        level_data: LevelData,
+    ) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.levels.insert((guild_id, user_id), level_data);
+
+        Ok(())
+    }
+
+    async fn add_message_xp(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        xp: u32,
+        now: DateTime<Utc>,
+        cooldown: Duration,
+        periods: &[String],
+    ) -> StorageResult<Option<(LevelData, LevelData)>> {
+        let mut data = self.data.lock().unwrap();
+        let old_data = data
+            .levels
+            .get(&(guild_id, user_id))
+            .cloned()
+            .unwrap_or_else(empty_level_data);
+        if now - old_data.last_msg < cooldown {
+            return Ok(None);
+        }
+
+        let mut new_data = old_data.clone();
+        new_data.msg_count += 1;
+        new_data.xp = new_data.xp.saturating_add(xp);
+        new_data.level = get_level_number(&self.curve, new_data.xp);
+        new_data.last_msg = now;
+        data.levels.insert((guild_id, user_id), new_data.clone());
+        data.add_period_xp(guild_id, user_id, xp, periods);
+
+        Ok(Some((old_data, new_data)))
+    }
+
+    async fn add_voice_xp(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        xp: u32,
+        minutes: u32,
+        periods: &[String],
+    ) -> StorageResult<(LevelData, LevelData)> {
+        let mut data = self.data.lock().unwrap();
+        let old_data = data
+            .levels
+            .get(&(guild_id, user_id))
+            .cloned()
+            .unwrap_or_else(empty_level_data);
+
+        let mut new_data = old_data.clone();
+        new_data.xp = new_data.xp.saturating_add(xp);
+        new_data.voice_minutes += minutes;
+        new_data.level = get_level_number(&self.curve, new_data.xp);
+        data.levels.insert((guild_id, user_id), new_data.clone());
+        data.add_period_xp(guild_id, user_id, xp, periods);
+
+        Ok((old_data, new_data))
+    }
+
+    async fn get_streak(&self, guild_id: u64, user_id: u64) -> StorageResult<Streak> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .streaks
+            .get(&(guild_id, user_id))
+            .copied()
+            .unwrap_or_default())
+    }
+
+    async fn set_streak(&self, guild_id: u64, user_id: u64, streak: Streak) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.streaks.insert((guild_id, user_id), streak);
+
+        Ok(())
+    }
+
+    async fn get_leaderboard_page(
+        &self,
+        guild_id: u64,
+        start: usize,
+        count: usize,
+    ) -> StorageResult<Vec<(u64, u32)>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .leaderboard(guild_id)
+            .into_iter()
+            .skip(start)
+            .take(count)
+            .collect())
+    }
+
+    async fn get_leaderboard_size(&self, guild_id: u64) -> StorageResult<usize> {
+        let data = self.data.lock().unwrap();
+        Ok(data.leaderboard(guild_id).len())
+    }
+
+    async fn get_user_rank(&self, guild_id: u64, user_id: u64) -> StorageResult<Option<usize>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .leaderboard(guild_id)
+            .iter()
+            .position(|(id, _)| *id == user_id))
+    }
+
+    async fn get_period_leaderboard_page(
+        &self,
+        guild_id: u64,
+        period: &str,
+        start: usize,
+        count: usize,
+    ) -> StorageResult<Vec<(u64, u32)>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .period_leaderboard(guild_id, period)
+            .into_iter()
+            .skip(start)
+            .take(count)
+            .collect())
+    }
+
+    async fn get_period_leaderboard_size(
+        &self,
+        guild_id: u64,
+        period: &str,
+    ) -> StorageResult<usize> {
+        let data = self.data.lock().unwrap();
+        Ok(data.period_leaderboard(guild_id, period).len())
+    }
+
+    async fn adjust_user_level(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        change: XpChange,
+        periods: &[String],
+    ) -> StorageResult<(LevelData, LevelData)> {
+        let mut data = self.data.lock().unwrap();
+        let old_data = data
+            .levels
+            .get(&(guild_id, user_id))
+            .cloned()
+            .unwrap_or_else(empty_level_data);
+
+        let mut new_data = old_data.clone();
+        change.apply(&mut new_data);
+        new_data.level = get_level_number(&self.curve, new_data.xp);
+        data.levels.insert((guild_id, user_id), new_data.clone());
+        let delta = new_data.xp as i64 - old_data.xp as i64;
+        if delta != 0 {
+            for period in periods {
+                let tally = data.periods.entry((guild_id, period.clone())).or_default();
+                let xp = tally.get(&user_id).copied().unwrap_or(0) as i64 + delta;
+                // Like the Redis script, anyone taken to zero drops off the board
+                if xp > 0 {
+                    tally.insert(user_id, xp as u32);
+                } else {
+                    tally.remove(&user_id);
+                }
+            }
+        }
+
+        Ok((old_data, new_data))
+    }
+
+    async fn reset_guild_levels(&self, guild_id: u64, periods: &[String]) -> StorageResult<usize> {
+        let mut data = self.data.lock().unwrap();
+        let before = data.levels.len();
+        data.levels.retain(|(guild, _), _| *guild != guild_id);
+        for period in periods {
+            data.periods.remove(&(guild_id, period.clone()));
+        }
+        data.activity.retain(|(guild, _, _), _| *guild != guild_id);
+        data.streaks.retain(|(guild, _), _| *guild != guild_id);
+
+        Ok(before - data.levels.len())
+    }
+
+    async fn migrate_global_levels(&self, _guild_id: u64) -> StorageResult<usize> {
+        // Nothing was ever stored globally in memory
+        Ok(0)
+    }
+
+    async fn record_activity(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        day: NaiveDate,
+        xp: u32,
+        messages: u32,
+    ) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        for user_id in &[Some(user_id), None] {
+            let totals = data.activity.entry((guild_id, *user_id, day)).or_default();
+            totals.0 += xp;
+            totals.1 += messages;
+        }
+
+        Ok(())
+    }
+
+    async fn get_activity(
+        &self,
+        guild_id: u64,
+        user_id: Option<u64>,
+        days: &[NaiveDate],
+    ) -> StorageResult<Vec<(u32, u32)>> {
+        let data = self.data.lock().unwrap();
+        Ok(days
+            .iter()
+            .map(|day| {
+                data.activity
+                    .get(&(guild_id, user_id, *day))
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect())
+    }
+
+    async fn add_xp_audit_entry(&self, guild_id: u64, entry: &XpAuditEntry) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.xp_audit
+            .entry(guild_id)
+            .or_default()
+            .insert(0, entry.clone());
+
+        Ok(())
+    }
+
+    async fn get_xp_audit_entries(
+        &self,
+        guild_id: u64,
+        count: usize,
+    ) -> StorageResult<Vec<XpAuditEntry>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .xp_audit
+            .get(&guild_id)
+            .map(|entries| entries.iter().take(count).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn add_abuse_entry(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        entry: &AbuseEntry,
+    ) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.abuse
+            .entry((guild_id, user_id))
+            .or_default()
+            .insert(0, entry.clone());
+
+        Ok(())
+    }
+
+    async fn get_abuse_entries(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        count: usize,
+    ) -> StorageResult<Vec<AbuseEntry>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .abuse
+            .get(&(guild_id, user_id))
+            .map(|entries| entries.iter().take(count).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn next_report_id(&self, guild_id: u64) -> StorageResult<u64> {
+        let mut data = self.data.lock().unwrap();
+        let id = data.report_ids.entry(guild_id).or_default();
+        *id += 1;
+
+        Ok(*id)
+    }
+
+    async fn set_report(&self, guild_id: u64, report: &Report) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.reports.insert((guild_id, report.id), report.clone());
+        data.reported_messages
+            .insert((guild_id, report.message_id), report.id);
+
+        Ok(())
+    }
+
+    async fn get_report(&self, guild_id: u64, id: u64) -> StorageResult<Option<Report>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.reports.get(&(guild_id, id)).cloned())
+    }
+
+    async fn get_message_report(
+        &self,
+        guild_id: u64,
+        message_id: u64,
+    ) -> StorageResult<Option<Report>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .reported_messages
+            .get(&(guild_id, message_id))
+            .and_then(|id| data.reports.get(&(guild_id, *id)))
+            .cloned())
+    }
+
+    async fn get_active_reports(&self, guild_id: u64) -> StorageResult<Vec<Report>> {
+        let data = self.data.lock().unwrap();
+        // Ordered by guild and then ID
+        Ok(data
+            .reports
+            .range((guild_id, 0)..=(guild_id, u64::MAX))
+            .map(|(_, report)| report)
+            .filter(|report| report.status.is_active())
+            .cloned()
+            .collect())
+    }
+
+    async fn add_report_audit_entry(
+        &self,
+        guild_id: u64,
+        entry: &ReportAuditEntry,
+    ) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.report_audit
+            .entry(guild_id)
+            .or_default()
+            .insert(0, entry.clone());
+
+        Ok(())
+    }
+
+    async fn get_report_audit_entries(
+        &self,
+        guild_id: u64,
+        count: usize,
+    ) -> StorageResult<Vec<ReportAuditEntry>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .report_audit
+            .get(&guild_id)
+            .map(|entries| entries.iter().take(count).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn get_setting(&self, guild_id: u64, name: &str) -> StorageResult<Option<String>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.settings.get(&(guild_id, name.to_string())).cloned())
+    }
+
+    async fn set_setting(&self, guild_id: u64, name: &str, value: &str) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.settings
+            .insert((guild_id, name.to_string()), value.to_string());
+
+        Ok(())
+    }
+
+    async fn clear_setting(&self, guild_id: u64, name: &str) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.settings.remove(&(guild_id, name.to_string()));
+
+        Ok(())
+    }
+
+    async fn get_user_setting(&self, user_id: u64, name: &str) -> StorageResult<Option<String>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .user_settings
+            .get(&(user_id, name.to_string()))
+            .cloned())
+    }
+
+    async fn set_user_setting(&self, user_id: u64, name: &str, value: &str) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.user_settings
+            .insert((user_id, name.to_string()), value.to_string());
+
+        Ok(())
+    }
+
+    async fn get_rewards(&self, guild_id: u64) -> StorageResult<Vec<(u32, RoleId)>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .rewards
+            .get(&guild_id)
+            .map(|rewards| rewards.iter().map(|(l, r)| (*l, *r)).collect())
+            .unwrap_or_default())
+    }
+
+    async fn set_reward(&self, guild_id: u64, level: u32, role: RoleId) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.rewards
+            .entry(guild_id)
+            .or_default()
+            .insert(level, role);
+
+        Ok(())
+    }
+
+    async fn remove_reward(&self, guild_id: u64, level: u32) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(rewards) = data.rewards.get_mut(&guild_id) {
+            rewards.remove(&level);
+        }
+
+        Ok(())
+    }
+
+    async fn get_xp_multipliers(&self, guild_id: u64) -> StorageResult<Vec<(XpTarget, f64)>> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .xp_multipliers
+            .get(&guild_id)
+            .map(|multipliers| multipliers.iter().map(|(t, m)| (*t, *m)).collect())
+            .unwrap_or_default())
+    }
+
+    async fn set_xp_multiplier(
+        &self,
+        guild_id: u64,
+        target: XpTarget,
+        multiplier: f64,
+    ) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.xp_multipliers
+            .entry(guild_id)
+            .or_default()
+            .insert(target, multiplier);
+
+        Ok(())
+    }
+
+    async fn remove_xp_multiplier(&self, guild_id: u64, target: XpTarget) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(multipliers) = data.xp_multipliers.get_mut(&guild_id) {
+            multipliers.remove(&target);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+//! Persistent bot state
+//!
+//! Everything the bot remembers goes through the [`Storage`] trait, so handlers and commands
+//! don't care whether it lives in Redis or in memory.
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serenity::{async_trait, model::id::RoleId};
+
+use crate::errors::GompeiError;
+use crate::util::abuse::AbuseEntry;
+use crate::util::audit::{XpAuditEntry, XpChange};
+use crate::util::leveling::LevelData;
+use crate::util::multipliers::XpTarget;
+use crate::util::reports::{Report, ReportAuditEntry};
+use crate::util::streaks::Streak;
+
+pub mod memory;
+pub mod redis_store;
+
+pub use memory::MemoryStorage;
+pub use redis_store::RedisStorage;
+
+pub type StorageResult<T> = Result<T, GompeiError>;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Gets a member's level data in a guild, all zeroes if they have none yet
+    async fn get_user_level(&self, guild_id: u64, user_id: u64) -> StorageResult<LevelData>;
+
+    /// Like [`Storage::get_user_level`], but for many members at once, in the same order
+    async fn get_user_levels(
+        &self,
+        guild_id: u64,
+        user_ids: &[u64],
+    ) -> StorageResult<Vec<LevelData>>;
+
+    /// Stores a member's level data and their position on the guild leaderboard
+    async fn set_user_level(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        level_data: LevelData,
+    ) -> StorageResult<()>;
+
+    /// Adds `xp` and one message to a member's totals and sets their last message time to `now`
+    /// in a single atomic step, unless their last message was less than `cooldown` ago
+    ///
+    /// The XP is also added to the member's tally on each of the `periods` boards. Returns the
+    /// member's level data from before and after, or `None` if nothing changed.
+    async fn add_message_xp(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        xp: u32,
+        now: DateTime<Utc>,
+        cooldown: Duration,
+        periods: &[String],
+    ) -> StorageResult<Option<(LevelData, LevelData)>>;
+
+    /// Adds `xp` and `minutes` of voice time to a member's totals in a single atomic step, along
+    /// with their tally on each of the `periods` boards
+    ///
+    /// Returns the member's level data from before and after.
+    async fn add_voice_xp(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        xp: u32,
+        minutes: u32,
+        periods: &[String],
+    ) -> StorageResult<(LevelData, LevelData)>;
+
+    /// Adds `xp` that wasn't earned by a message or voice time, such as a streak bonus, along
+    /// with the member's tally on each of the `periods` boards
+    ///
+    /// Returns the member's level data from before and after.
+    async fn add_bonus_xp(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        xp: u32,
+        periods: &[String],
+    ) -> StorageResult<(LevelData, LevelData)> {
+        // Voice XP without any minutes is just XP
+        self.add_voice_xp(guild_id, user_id, xp, 0, periods).await
+    }
+
+    /// Gets a member's posting streak, all zeroes if they have none yet
+    async fn get_streak(&self, guild_id: u64, user_id: u64) -> StorageResult<Streak>;
+
+    async fn set_streak(&self, guild_id: u64, user_id: u64, streak: Streak) -> StorageResult<()>;
+
+    /// Gets `count` `(user_id, xp)` pairs from the guild leaderboard, highest XP first,
+    /// starting at the zero-based position `start`
+    async fn get_leaderboard_page(
+        &self,
+        guild_id: u64,
+        start: usize,
+        count: usize,
+    ) -> StorageResult<Vec<(u64, u32)>>;
+
+    /// Gets the number of members on the guild leaderboard
+    async fn get_leaderboard_size(&self, guild_id: u64) -> StorageResult<usize>;
+
+    /// Gets a member's zero-based position on the guild leaderboard, if they have any XP
+    async fn get_user_rank(&self, guild_id: u64, user_id: u64) -> StorageResult<Option<usize>>;
+
+    /// Like [`Storage::get_leaderboard_page`], but for XP earned during one period
+    async fn get_period_leaderboard_page(
+        &self,
+        guild_id: u64,
+        period: &str,
+        start: usize,
+        count: usize,
+    ) -> StorageResult<Vec<(u64, u32)>>;
+
+    /// Gets the number of members who earned XP during a period
+    async fn get_period_leaderboard_size(
+        &self,
+        guild_id: u64,
+        period: &str,
+    ) -> StorageResult<usize>;
+
+    /// Applies a staff change to a member's totals in a single atomic step, moving their tally on
+    /// each of the `periods` boards by the same amount of XP
+    ///
+    /// Period tallies never go below zero. Returns the member's level data from before and after.
+    async fn adjust_user_level(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        change: XpChange,
+        periods: &[String],
+    ) -> StorageResult<(LevelData, LevelData)>;
+
+    /// Wipes every member's level data, streak and activity history in a guild, including members
+    /// without any XP, along with the `periods` leaderboards, returning the number of members reset
+    ///
+    /// Only the boards in progress should be passed in, so finished weeks, months and seasons are
+    /// kept.
+    async fn reset_guild_levels(&self, guild_id: u64, periods: &[String]) -> StorageResult<usize>;
+
+    /// Moves XP stored before per-guild leveling into the given guild, returning the number of
+    /// members migrated
+    async fn migrate_global_levels(&self, guild_id: u64) -> StorageResult<usize>;
+
+    /// Adds to a member's and their guild's totals for one day
+    async fn record_activity(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        day: NaiveDate,
+        xp: u32,
+        messages: u32,
+    ) -> StorageResult<()>;
+
+    /// Gets `(xp, messages)` for each of `days`, for one member or the whole guild if `user_id`
+    /// is `None`
+    async fn get_activity(
+        &self,
+        guild_id: u64,
+        user_id: Option<u64>,
+        days: &[NaiveDate],
+    ) -> StorageResult<Vec<(u32, u32)>>;
+
+    /// Records a staff change to XP in the guild's audit log
+    async fn add_xp_audit_entry(&self, guild_id: u64, entry: &XpAuditEntry) -> StorageResult<()>;
+
+    /// Gets up to `count` audit log entries, newest first
+    async fn get_xp_audit_entries(
+        &self,
+        guild_id: u64,
+        count: usize,
+    ) -> StorageResult<Vec<XpAuditEntry>>;
+
+    /// Records that a member's message was refused XP or that they were flagged
+    async fn add_abuse_entry(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        entry: &AbuseEntry,
+    ) -> StorageResult<()>;
+
+    /// Gets up to `count` of a member's refusals and flags, newest first
+    async fn get_abuse_entries(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        count: usize,
+    ) -> StorageResult<Vec<AbuseEntry>>;
+
+    /// Reserves the next report ID in a guild, counting up from 1
+    async fn next_report_id(&self, guild_id: u64) -> StorageResult<u64>;
+
+    /// Stores a report, replacing any with the same ID
+    async fn set_report(&self, guild_id: u64, report: &Report) -> StorageResult<()>;
+
+    async fn get_report(&self, guild_id: u64, id: u64) -> StorageResult<Option<Report>>;
+
+    /// Gets the report for a message, if it's been reported before
+    async fn get_message_report(
+        &self,
+        guild_id: u64,
+        message_id: u64,
+    ) -> StorageResult<Option<Report>>;
+
+    /// Gets the reports still open or claimed, oldest first
+    async fn get_active_reports(&self, guild_id: u64) -> StorageResult<Vec<Report>>;
+
+    /// Records who reported a message in the guild's report log
+    async fn add_report_audit_entry(
+        &self,
+        guild_id: u64,
+        entry: &ReportAuditEntry,
+    ) -> StorageResult<()>;
+
+    /// Gets up to `count` report log entries, newest first
+    async fn get_report_audit_entries(
+        &self,
+        guild_id: u64,
+        count: usize,
+    ) -> StorageResult<Vec<ReportAuditEntry>>;
+
+    async fn get_setting(&self, guild_id: u64, name: &str) -> StorageResult<Option<String>>;
+
+    /// Like [`Storage::get_setting`], but for several settings at once, in the same order
+    async fn get_settings(
+        &self,
+        guild_id: u64,
+        names: &[&str],
+    ) -> StorageResult<Vec<Option<String>>> {
+        let mut values = Vec::with_capacity(names.len());
+        for name in names {
+            values.push(self.get_setting(guild_id, name).await?);
+        }
+
+        Ok(values)
+    }
+
+    async fn set_setting(&self, guild_id: u64, name: &str, value: &str) -> StorageResult<()>;
+
+    async fn clear_setting(&self, guild_id: u64, name: &str) -> StorageResult<()>;
+
+    /// Gets one of a user's own preferences, which follow them across guilds
+    async fn get_user_setting(&self, user_id: u64, name: &str) -> StorageResult<Option<String>>;
+
+    async fn set_user_setting(&self, user_id: u64, name: &str, value: &str) -> StorageResult<()>;
+
+    /// Gets a guild's `(level, role)` rewards, lowest level first
+    async fn get_rewards(&self, guild_id: u64) -> StorageResult<Vec<(u32, RoleId)>>;
+
+    async fn set_reward(&self, guild_id: u64, level: u32, role: RoleId) -> StorageResult<()>;
+
+    async fn remove_reward(&self, guild_id: u64, level: u32) -> StorageResult<()>;
+
+    /// Gets a guild's XP multipliers. Zero means no XP.
+    async fn get_xp_multipliers(&self, guild_id: u64) -> StorageResult<Vec<(XpTarget, f64)>>;
+
+    async fn set_xp_multiplier(
+        &self,
+        guild_id: u64,
+        target: XpTarget,
+        multiplier: f64,
+    ) -> StorageResult<()>;
+
+    async fn remove_xp_multiplier(&self, guild_id: u64, target: XpTarget) -> StorageResult<()>;
+}
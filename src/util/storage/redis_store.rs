@@ -0,0 +1,996 @@
+use chrono::{prelude::*, Duration};
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serenity::{async_trait, model::id::RoleId};
+use std::collections::HashSet;
+use tracing::{info, instrument};
+
+use super::{Storage, StorageResult};
+use crate::errors::GompeiError;
+use crate::util::abuse::AbuseEntry;
+use crate::util::audit::{XpAuditEntry, XpChange};
+use crate::util::leveling::{get_level_number, LevelCurve, LevelData};
+use crate::util::multipliers::XpTarget;
+use crate::util::reports::{Report, ReportAuditEntry};
+use crate::util::streaks::Streak;
+
+/// Builds the Redis key for one of a member's per-guild leveling fields
+fn level_key(guild_id: u64, user_id: u64, field: &str) -> String {
+    format!("{}:{}:{}", guild_id, user_id, field)
+}
+
+/// Builds the Redis key for a guild's XP sorted set
+fn leaderboard_key(guild_id: u64) -> String {
+    format!("{}:leaderboard", guild_id)
+}
+
+/// Builds the Redis key for a guild's sorted set of XP earned during one period
+fn period_key(guild_id: u64, period: &str) -> String {
+    format!("{}:period:{}", guild_id, period)
+}
+
+/// Builds the Redis key for a guild's settings hash
+fn config_key(guild_id: u64) -> String {
+    format!("{}:config", guild_id)
+}
+
+/// Builds the Redis key for a member's daily XP and message counts hash, or the guild's if
+/// `user_id` is `None`
+///
+/// Fields are `<date>:xp` and `<date>:messages`.
+fn activity_key(guild_id: u64, user_id: Option<u64>) -> String {
+    match user_id {
+        Some(user_id) => level_key(guild_id, user_id, "activity"),
+        None => format!("{}:activity", guild_id),
+    }
+}
+
+/// Builds the Redis key for a guild's XP audit log list
+fn xp_audit_key(guild_id: u64) -> String {
+    format!("{}:xp_audit", guild_id)
+}
+
+/// Audit entries kept per guild before the oldest are dropped
+const XP_AUDIT_LENGTH: isize = 1000;
+
+/// Refusals and flags kept per member before the oldest are dropped
+const ABUSE_LOG_LENGTH: isize = 100;
+
+/// Builds the Redis key for the last report ID handed out in a guild
+fn report_id_key(guild_id: u64) -> String {
+    format!("{}:report_id", guild_id)
+}
+
+/// Builds the Redis key for a guild's reports hash, which maps IDs to JSON
+fn reports_key(guild_id: u64) -> String {
+    format!("{}:reports", guild_id)
+}
+
+/// Builds the Redis key for the hash of reported message IDs to report IDs in a guild
+fn reported_messages_key(guild_id: u64) -> String {
+    format!("{}:reported_messages", guild_id)
+}
+
+/// Builds the Redis key for the set of a guild's open and claimed report IDs
+fn active_reports_key(guild_id: u64) -> String {
+    format!("{}:active_reports", guild_id)
+}
+
+/// Builds the Redis key for a guild's list of who reported what
+fn report_audit_key(guild_id: u64) -> String {
+    format!("{}:report_audit", guild_id)
+}
+
+/// Report log entries kept per guild before the oldest are dropped
+const REPORT_AUDIT_LENGTH: isize = 1000;
+
+/// Builds the Redis key for a user's preferences hash
+fn user_config_key(user_id: u64) -> String {
+    format!("user:{}:config", user_id)
+}
+
+/// Builds the Redis key for a guild's reward hash
+fn rewards_key(guild_id: u64) -> String {
+    format!("{}:rewards", guild_id)
+}
+
+/// Builds the Redis key for a guild's XP multipliers hash
+fn multipliers_key(guild_id: u64) -> String {
+    format!("{}:xp_multipliers", guild_id)
+}
+
+/// Awards XP for a message if the member is off cooldown, all in one step
+///
+/// KEYS: count, exp, last, voice, leaderboard, then any period leaderboards. ARGV: user ID, XP
+/// to add, now, cooldown in seconds. Returns the totals from before the message and whether
+/// anything was awarded. XP stops at the largest `u32`, so it always reads back.
+const ADD_MESSAGE_XP_SCRIPT: &str = r"
+local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+local exp = tonumber(redis.call('GET', KEYS[2]) or '0')
+local last = tonumber(redis.call('GET', KEYS[3]) or '0')
+local voice = tonumber(redis.call('GET', KEYS[4]) or '0')
+local now = tonumber(ARGV[3])
+
+if now - last < tonumber(ARGV[4]) then
+    return {count, exp, last, voice, 0}
+end
+
+local new_exp = math.min(exp + tonumber(ARGV[2]), 4294967295)
+redis.call('SET', KEYS[1], count + 1)
+redis.call('SET', KEYS[2], new_exp)
+redis.call('SET', KEYS[3], now)
+redis.call('ZADD', KEYS[5], new_exp, ARGV[1])
+for i = 6, #KEYS do
+    redis.call('ZINCRBY', KEYS[i], ARGV[2], ARGV[1])
+end
+
+return {count, exp, last, voice, 1}
+";
+
+/// Awards XP for time spent in voice, all in one step
+///
+/// KEYS: count, exp, last, voice, leaderboard, then any period leaderboards. ARGV: user ID, XP to
+/// add, minutes to add. Returns the totals from before the award. XP stops at the largest `u32`,
+/// like it does for messages.
+const ADD_VOICE_XP_SCRIPT: &str = r"
+local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+local exp = tonumber(redis.call('GET', KEYS[2]) or '0')
+local last = tonumber(redis.call('GET', KEYS[3]) or '0')
+local voice = tonumber(redis.call('GET', KEYS[4]) or '0')
+
+local new_exp = math.min(exp + tonumber(ARGV[2]), 4294967295)
+redis.call('SET', KEYS[2], new_exp)
+redis.call('SET', KEYS[4], voice + tonumber(ARGV[3]))
+redis.call('ZADD', KEYS[5], new_exp, ARGV[1])
+for i = 6, #KEYS do
+    redis.call('ZINCRBY', KEYS[i], ARGV[2], ARGV[1])
+end
+
+return {count, exp, last, voice}
+";
+
+/// Applies a staff change to a member's totals, all in one step
+///
+/// KEYS: count, exp, last, voice, leaderboard, then any period leaderboards. ARGV: user ID,
+/// action (`give`, `take`, `set`, `messages` or `reset`), amount. Returns the totals from before
+/// the change, which [`XpChange::apply`] turns into the totals after.
+const ADJUST_XP_SCRIPT: &str = r"
+local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+local exp = tonumber(redis.call('GET', KEYS[2]) or '0')
+local last = tonumber(redis.call('GET', KEYS[3]) or '0')
+local voice = tonumber(redis.call('GET', KEYS[4]) or '0')
+local action = ARGV[2]
+local amount = tonumber(ARGV[3])
+
+local new_count, new_exp, new_voice = count, exp, voice
+if action == 'give' then
+    new_exp = math.min(exp + amount, 4294967295)
+elseif action == 'take' then
+    new_exp = math.max(exp - amount, 0)
+elseif action == 'set' then
+    new_exp = amount
+elseif action == 'messages' then
+    new_count = amount
+elseif action == 'reset' then
+    new_count, new_exp, new_voice = 0, 0, 0
+end
+
+redis.call('SET', KEYS[1], new_count)
+redis.call('SET', KEYS[2], new_exp)
+redis.call('SET', KEYS[4], new_voice)
+if new_exp > 0 then
+    redis.call('ZADD', KEYS[5], new_exp, ARGV[1])
+else
+    redis.call('ZREM', KEYS[5], ARGV[1])
+end
+local delta = new_exp - exp
+if delta ~= 0 then
+    for i = 6, #KEYS do
+        local tally = tonumber(redis.call('ZINCRBY', KEYS[i], delta, ARGV[1]))
+        if tally <= 0 then
+            redis.call('ZREM', KEYS[i], ARGV[1])
+        end
+    end
+end
+
+return {count, exp, last, voice}
+";
+
+/// Per-member keys that a guild reset wipes, as in `{guild_id}:{user_id}:{field}`
+const RESET_FIELDS: [&str; 6] = ["count", "exp", "last", "voice", "streak", "activity"];
+
+/// Storage backed by Redis
+///
+/// The connection manager multiplexes over one connection and reconnects on its own, so each
+/// call works on a cheap clone of it.
+#[derive(Clone)]
+pub struct RedisStorage {
+    conn: ConnectionManager,
+    curve: LevelCurve,
+    add_message_xp_script: redis::Script,
+    adjust_xp_script: redis::Script,
+    add_voice_xp_script: redis::Script,
+}
+
+impl RedisStorage {
+    pub fn new(conn: ConnectionManager, curve: LevelCurve) -> RedisStorage {
+        RedisStorage {
+            conn,
+            curve,
+            add_message_xp_script: redis::Script::new(ADD_MESSAGE_XP_SCRIPT),
+            adjust_xp_script: redis::Script::new(ADJUST_XP_SCRIPT),
+            add_voice_xp_script: redis::Script::new(ADD_VOICE_XP_SCRIPT),
+        }
+    }
+
+    fn level_data(
+        &self,
+        msg_count: u32,
+        xp: u32,
+        last_msg_num: i64,
+        voice_minutes: u32,
+    ) -> LevelData {
+        LevelData {
+            msg_count,
+            xp,
+            level: get_level_number(&self.curve, xp),
+            // A corrupt timestamp just means no cooldown
+            last_msg: Utc
+                .timestamp_opt(last_msg_num, 0)
+                .single()
+                .unwrap_or(DateTime::<Utc>::MIN_UTC),
+            voice_minutes,
+        }
+    }
+
+    /// Adds the keys every XP script works on, in the order they expect them
+    fn level_script_keys(
+        &self,
+        invocation: &mut redis::ScriptInvocation,
+        guild_id: u64,
+        user_id: u64,
+        periods: &[String],
+    ) {
+        invocation
+            .key(level_key(guild_id, user_id, "count"))
+            .key(level_key(guild_id, user_id, "exp"))
+            .key(level_key(guild_id, user_id, "last"))
+            .key(level_key(guild_id, user_id, "voice"))
+            .key(leaderboard_key(guild_id));
+        for period in periods {
+            invocation.key(period_key(guild_id, period));
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn get_user_level(&self, guild_id: u64, user_id: u64) -> StorageResult<LevelData> {
+        let mut redis_conn = self.conn.clone();
+        let (msg_count, xp, last_msg_num, voice_minutes): (
+            Option<u32>,
+            Option<u32>,
+            Option<i64>,
+            Option<u32>,
+        ) = redis::cmd("MGET")
+            .arg(level_key(guild_id, user_id, "count"))
+            .arg(level_key(guild_id, user_id, "exp"))
+            .arg(level_key(guild_id, user_id, "last"))
+            .arg(level_key(guild_id, user_id, "voice"))
+            .query_async(&mut redis_conn)
+            .await?;
+
+        Ok(self.level_data(
+            msg_count.unwrap_or(0),
+            xp.unwrap_or(0),
+            last_msg_num.unwrap_or(0),
+            voice_minutes.unwrap_or(0),
+        ))
+    }
+
+    async fn get_user_levels(
+        &self,
+        guild_id: u64,
+        user_ids: &[u64],
+    ) -> StorageResult<Vec<LevelData>> {
+        if user_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut redis_conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        for user_id in user_ids {
+            pipe.cmd("MGET")
+                .arg(level_key(guild_id, *user_id, "count"))
+                .arg(level_key(guild_id, *user_id, "exp"))
+                .arg(level_key(guild_id, *user_id, "last"))
+                .arg(level_key(guild_id, *user_id, "voice"));
+        }
+        let levels: Vec<(Option<u32>, Option<u32>, Option<i64>, Option<u32>)> =
+            pipe.query_async(&mut redis_conn).await?;
+
+        Ok(levels
+            .into_iter()
+            .map(|(msg_count, xp, last_msg_num, voice_minutes)| {
+                self.level_data(
+                    msg_count.unwrap_or(0),
+                    xp.unwrap_or(0),
+                    last_msg_num.unwrap_or(0),
+                    voice_minutes.unwrap_or(0),
+                )
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn set_user_level(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        level_data: LevelData,
+    ) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .set(level_key(guild_id, user_id, "count"), level_data.msg_count)
+            .ignore()
+            .set(level_key(guild_id, user_id, "exp"), level_data.xp)
+            .ignore()
+            .set(
+                level_key(guild_id, user_id, "last"),
+                level_data.last_msg.timestamp(),
+            )
+            .ignore()
+            .set(
+                level_key(guild_id, user_id, "voice"),
+                level_data.voice_minutes,
+            )
+            .ignore();
+        // Members without XP stay off the leaderboard
+        if level_data.xp > 0 {
+            pipe.zadd(leaderboard_key(guild_id), user_id, level_data.xp);
+        } else {
+            pipe.zrem(leaderboard_key(guild_id), user_id);
+        }
+        pipe.ignore().query_async::<_, ()>(&mut redis_conn).await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn add_message_xp(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        xp: u32,
+        now: DateTime<Utc>,
+        cooldown: Duration,
+        periods: &[String],
+    ) -> StorageResult<Option<(LevelData, LevelData)>> {
+        let mut redis_conn = self.conn.clone();
+        let mut invocation = self.add_message_xp_script.prepare_invoke();
+        self.level_script_keys(&mut invocation, guild_id, user_id, periods);
+        let (msg_count, old_xp, last_msg_num, voice_minutes, awarded): (u32, u32, i64, u32, bool) =
+            invocation
+                .arg(user_id)
+                .arg(xp)
+                .arg(now.timestamp())
+                .arg(cooldown.num_seconds())
+                .invoke_async(&mut redis_conn)
+                .await?;
+
+        if !awarded {
+            return Ok(None);
+        }
+
+        Ok(Some((
+            self.level_data(msg_count, old_xp, last_msg_num, voice_minutes),
+            self.level_data(
+                msg_count + 1,
+                old_xp.saturating_add(xp),
+                now.timestamp(),
+                voice_minutes,
+            ),
+        )))
+    }
+
+    #[instrument(skip(self))]
+    async fn add_voice_xp(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        xp: u32,
+        minutes: u32,
+        periods: &[String],
+    ) -> StorageResult<(LevelData, LevelData)> {
+        let mut redis_conn = self.conn.clone();
+        let mut invocation = self.add_voice_xp_script.prepare_invoke();
+        self.level_script_keys(&mut invocation, guild_id, user_id, periods);
+        let (msg_count, old_xp, last_msg_num, voice_minutes): (u32, u32, i64, u32) = invocation
+            .arg(user_id)
+            .arg(xp)
+            .arg(minutes)
+            .invoke_async(&mut redis_conn)
+            .await?;
+
+        Ok((
+            self.level_data(msg_count, old_xp, last_msg_num, voice_minutes),
+            self.level_data(
+                msg_count,
+                old_xp.saturating_add(xp),
+                last_msg_num,
+                voice_minutes + minutes,
+            ),
+        ))
+    }
+
+    async fn get_streak(&self, guild_id: u64, user_id: u64) -> StorageResult<Streak> {
+        let mut redis_conn = self.conn.clone();
+        let (current, longest): (Option<u32>, Option<u32>) = redis_conn
+            .hget(
+                level_key(guild_id, user_id, "streak"),
+                &["current", "longest"],
+            )
+            .await?;
+
+        Ok(Streak {
+            current: current.unwrap_or(0),
+            longest: longest.unwrap_or(0),
+        })
+    }
+
+    async fn set_streak(&self, guild_id: u64, user_id: u64, streak: Streak) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        redis_conn
+            .hset_multiple::<_, _, _, ()>(
+                level_key(guild_id, user_id, "streak"),
+                &[("current", streak.current), ("longest", streak.longest)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_leaderboard_page(
+        &self,
+        guild_id: u64,
+        start: usize,
+        count: usize,
+    ) -> StorageResult<Vec<(u64, u32)>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn
+            .zrevrange_withscores(
+                leaderboard_key(guild_id),
+                start as isize,
+                (start + count - 1) as isize,
+            )
+            .await?)
+    }
+
+    async fn get_leaderboard_size(&self, guild_id: u64) -> StorageResult<usize> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn.zcard(leaderboard_key(guild_id)).await?)
+    }
+
+    async fn get_user_rank(&self, guild_id: u64, user_id: u64) -> StorageResult<Option<usize>> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn
+            .zrevrank(leaderboard_key(guild_id), user_id)
+            .await?)
+    }
+
+    async fn get_period_leaderboard_page(
+        &self,
+        guild_id: u64,
+        period: &str,
+        start: usize,
+        count: usize,
+    ) -> StorageResult<Vec<(u64, u32)>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn
+            .zrevrange_withscores(
+                period_key(guild_id, period),
+                start as isize,
+                (start + count - 1) as isize,
+            )
+            .await?)
+    }
+
+    async fn get_period_leaderboard_size(
+        &self,
+        guild_id: u64,
+        period: &str,
+    ) -> StorageResult<usize> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn.zcard(period_key(guild_id, period)).await?)
+    }
+
+    #[instrument(skip(self))]
+    async fn adjust_user_level(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        change: XpChange,
+        periods: &[String],
+    ) -> StorageResult<(LevelData, LevelData)> {
+        let (action, amount) = match change {
+            XpChange::Give(xp) => ("give", xp),
+            XpChange::Take(xp) => ("take", xp),
+            XpChange::Set(xp) => ("set", xp),
+            XpChange::SetMessages(count) => ("messages", count),
+            XpChange::Reset => ("reset", 0),
+        };
+        let mut redis_conn = self.conn.clone();
+        let mut invocation = self.adjust_xp_script.prepare_invoke();
+        self.level_script_keys(&mut invocation, guild_id, user_id, periods);
+        let (msg_count, xp, last_msg_num, voice_minutes): (u32, u32, i64, u32) = invocation
+            .arg(user_id)
+            .arg(action)
+            .arg(amount)
+            .invoke_async(&mut redis_conn)
+            .await?;
+
+        let old_data = self.level_data(msg_count, xp, last_msg_num, voice_minutes);
+        let mut new_data = old_data.clone();
+        change.apply(&mut new_data);
+        new_data.level = get_level_number(&self.curve, new_data.xp);
+
+        Ok((old_data, new_data))
+    }
+
+    #[instrument(skip(self))]
+    async fn reset_guild_levels(&self, guild_id: u64, periods: &[String]) -> StorageResult<usize> {
+        let mut redis_conn = self.conn.clone();
+        // Members without XP aren't on the leaderboard, so look for their keys instead
+        let mut user_ids = HashSet::new();
+        let mut keys = vec![leaderboard_key(guild_id), activity_key(guild_id, None)];
+        keys.extend(periods.iter().map(|period| period_key(guild_id, period)));
+        {
+            let mut scan = redis_conn
+                .scan_match::<_, String>(format!("{}:*", guild_id))
+                .await?;
+            while let Some(key) = scan.next_item().await {
+                let parts: Vec<&str> = key.splitn(3, ':').collect();
+                match parts.as_slice() {
+                    [_, user_id, field] if RESET_FIELDS.contains(field) => {
+                        if let Ok(user_id) = user_id.parse::<u64>() {
+                            user_ids.insert(user_id);
+                            keys.push(key);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for key in &keys {
+            pipe.del(key).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut redis_conn).await?;
+
+        info!("Reset {} members in guild {}", user_ids.len(), guild_id);
+
+        Ok(user_ids.len())
+    }
+
+    /// Moves the old global `{user_id}:exp`, `{user_id}:count` and `{user_id}:last` keys
+    /// into the given guild
+    ///
+    /// Existing per-guild data for a migrated member is overwritten.
+    #[instrument(skip(self))]
+    async fn migrate_global_levels(&self, guild_id: u64) -> StorageResult<usize> {
+        let mut redis_conn = self.conn.clone();
+
+        // Only the old keys have a bare user ID in front of the field name
+        let mut user_ids: Vec<u64> = Vec::new();
+        {
+            let mut keys = redis_conn.scan_match::<&str, String>("*:exp").await?;
+            while let Some(key) = keys.next_item().await {
+                let parts: Vec<&str> = key.split(':').collect();
+                if let [user_id, "exp"] = parts.as_slice() {
+                    if let Ok(user_id) = user_id.parse::<u64>() {
+                        user_ids.push(user_id);
+                    }
+                }
+            }
+        }
+
+        for user_id in &user_ids {
+            for field in &["count", "exp", "last"] {
+                let old_key = format!("{}:{}", user_id, field);
+                if redis_conn.exists::<_, bool>(&old_key).await? {
+                    redis_conn
+                        .rename::<_, ()>(old_key.clone(), level_key(guild_id, *user_id, field))
+                        .await?;
+                }
+            }
+
+            let xp: u32 = redis_conn
+                .get::<_, Option<u32>>(level_key(guild_id, *user_id, "exp"))
+                .await?
+                .unwrap_or(0);
+            if xp > 0 {
+                redis_conn
+                    .zadd::<_, _, _, ()>(leaderboard_key(guild_id), *user_id, xp)
+                    .await?;
+            }
+        }
+
+        info!(
+            "Migrated {} members into guild {}",
+            user_ids.len(),
+            guild_id
+        );
+
+        Ok(user_ids.len())
+    }
+
+    async fn record_activity(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        day: NaiveDate,
+        xp: u32,
+        messages: u32,
+    ) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        let xp_field = format!("{}:xp", day);
+        let messages_field = format!("{}:messages", day);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for key in &[
+            activity_key(guild_id, Some(user_id)),
+            activity_key(guild_id, None),
+        ] {
+            pipe.hincr(key, &xp_field, xp).ignore();
+            if messages > 0 {
+                pipe.hincr(key, &messages_field, messages).ignore();
+            }
+        }
+        pipe.query_async::<_, ()>(&mut redis_conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_activity(
+        &self,
+        guild_id: u64,
+        user_id: Option<u64>,
+        days: &[NaiveDate],
+    ) -> StorageResult<Vec<(u32, u32)>> {
+        if days.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut redis_conn = self.conn.clone();
+        let mut cmd = redis::cmd("HMGET");
+        cmd.arg(activity_key(guild_id, user_id));
+        for day in days {
+            cmd.arg(format!("{}:xp", day))
+                .arg(format!("{}:messages", day));
+        }
+        let values: Vec<Option<u32>> = cmd.query_async(&mut redis_conn).await?;
+
+        Ok(values
+            .chunks(2)
+            .map(|day| (day[0].unwrap_or(0), day[1].unwrap_or(0)))
+            .collect())
+    }
+
+    async fn add_xp_audit_entry(&self, guild_id: u64, entry: &XpAuditEntry) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        let entry =
+            serde_json::to_string(entry).map_err(|e| GompeiError::GenericError(e.to_string()))?;
+        redis::pipe()
+            .atomic()
+            .lpush(xp_audit_key(guild_id), entry)
+            .ignore()
+            .ltrim(xp_audit_key(guild_id), 0, XP_AUDIT_LENGTH - 1)
+            .ignore()
+            .query_async::<_, ()>(&mut redis_conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_xp_audit_entries(
+        &self,
+        guild_id: u64,
+        count: usize,
+    ) -> StorageResult<Vec<XpAuditEntry>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut redis_conn = self.conn.clone();
+        let entries: Vec<String> = redis_conn
+            .lrange(xp_audit_key(guild_id), 0, count as isize - 1)
+            .await?;
+
+        entries
+            .iter()
+            .map(|entry| {
+                serde_json::from_str(entry).map_err(|e| GompeiError::GenericError(e.to_string()))
+            })
+            .collect()
+    }
+
+    async fn add_abuse_entry(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        entry: &AbuseEntry,
+    ) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        let key = level_key(guild_id, user_id, "abuse");
+        let entry =
+            serde_json::to_string(entry).map_err(|e| GompeiError::GenericError(e.to_string()))?;
+        redis::pipe()
+            .atomic()
+            .lpush(&key, entry)
+            .ignore()
+            .ltrim(&key, 0, ABUSE_LOG_LENGTH - 1)
+            .ignore()
+            .query_async::<_, ()>(&mut redis_conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_abuse_entries(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        count: usize,
+    ) -> StorageResult<Vec<AbuseEntry>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut redis_conn = self.conn.clone();
+        let entries: Vec<String> = redis_conn
+            .lrange(level_key(guild_id, user_id, "abuse"), 0, count as isize - 1)
+            .await?;
+
+        entries
+            .iter()
+            .map(|entry| {
+                serde_json::from_str(entry).map_err(|e| GompeiError::GenericError(e.to_string()))
+            })
+            .collect()
+    }
+
+    async fn next_report_id(&self, guild_id: u64) -> StorageResult<u64> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn.incr(report_id_key(guild_id), 1).await?)
+    }
+
+    async fn set_report(&self, guild_id: u64, report: &Report) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        let json =
+            serde_json::to_string(report).map_err(|e| GompeiError::GenericError(e.to_string()))?;
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .hset(reports_key(guild_id), report.id, json)
+            .ignore()
+            .hset(
+                reported_messages_key(guild_id),
+                report.message_id,
+                report.id,
+            )
+            .ignore();
+        if report.status.is_active() {
+            pipe.sadd(active_reports_key(guild_id), report.id).ignore();
+        } else {
+            pipe.srem(active_reports_key(guild_id), report.id).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut redis_conn).await?;
+
+        Ok(())
+    }
+
+    async fn get_report(&self, guild_id: u64, id: u64) -> StorageResult<Option<Report>> {
+        let mut redis_conn = self.conn.clone();
+        let json: Option<String> = redis_conn.hget(reports_key(guild_id), id).await?;
+
+        json.map(|json| {
+            serde_json::from_str(&json).map_err(|e| GompeiError::GenericError(e.to_string()))
+        })
+        .transpose()
+    }
+
+    async fn get_message_report(
+        &self,
+        guild_id: u64,
+        message_id: u64,
+    ) -> StorageResult<Option<Report>> {
+        let mut redis_conn = self.conn.clone();
+        let id: Option<u64> = redis_conn
+            .hget(reported_messages_key(guild_id), message_id)
+            .await?;
+
+        match id {
+            Some(id) => self.get_report(guild_id, id).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn get_active_reports(&self, guild_id: u64) -> StorageResult<Vec<Report>> {
+        let mut redis_conn = self.conn.clone();
+        let mut ids: Vec<u64> = redis_conn.smembers(active_reports_key(guild_id)).await?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        ids.sort_unstable();
+
+        let mut cmd = redis::cmd("HMGET");
+        cmd.arg(reports_key(guild_id)).arg(&ids);
+        let reports: Vec<Option<String>> = cmd.query_async(&mut redis_conn).await?;
+
+        reports
+            .iter()
+            .flatten()
+            .map(|json| {
+                serde_json::from_str(json).map_err(|e| GompeiError::GenericError(e.to_string()))
+            })
+            .collect()
+    }
+
+    async fn add_report_audit_entry(
+        &self,
+        guild_id: u64,
+        entry: &ReportAuditEntry,
+    ) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        let entry =
+            serde_json::to_string(entry).map_err(|e| GompeiError::GenericError(e.to_string()))?;
+        redis::pipe()
+            .atomic()
+            .lpush(report_audit_key(guild_id), entry)
+            .ignore()
+            .ltrim(report_audit_key(guild_id), 0, REPORT_AUDIT_LENGTH - 1)
+            .ignore()
+            .query_async::<_, ()>(&mut redis_conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_report_audit_entries(
+        &self,
+        guild_id: u64,
+        count: usize,
+    ) -> StorageResult<Vec<ReportAuditEntry>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut redis_conn = self.conn.clone();
+        let entries: Vec<String> = redis_conn
+            .lrange(report_audit_key(guild_id), 0, count as isize - 1)
+            .await?;
+
+        entries
+            .iter()
+            .map(|entry| {
+                serde_json::from_str(entry).map_err(|e| GompeiError::GenericError(e.to_string()))
+            })
+            .collect()
+    }
+
+    async fn get_setting(&self, guild_id: u64, name: &str) -> StorageResult<Option<String>> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn.hget(config_key(guild_id), name).await?)
+    }
+
+    async fn get_settings(
+        &self,
+        guild_id: u64,
+        names: &[&str],
+    ) -> StorageResult<Vec<Option<String>>> {
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut redis_conn = self.conn.clone();
+        let mut cmd = redis::cmd("HMGET");
+        cmd.arg(config_key(guild_id)).arg(names);
+        Ok(cmd.query_async(&mut redis_conn).await?)
+    }
+
+    async fn set_setting(&self, guild_id: u64, name: &str, value: &str) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn.hset(config_key(guild_id), name, value).await?)
+    }
+
+    async fn clear_setting(&self, guild_id: u64, name: &str) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn.hdel(config_key(guild_id), name).await?)
+    }
+
+    async fn get_user_setting(&self, user_id: u64, name: &str) -> StorageResult<Option<String>> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn.hget(user_config_key(user_id), name).await?)
+    }
+
+    async fn set_user_setting(&self, user_id: u64, name: &str, value: &str) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn
+            .hset(user_config_key(user_id), name, value)
+            .await?)
+    }
+
+    async fn get_rewards(&self, guild_id: u64) -> StorageResult<Vec<(u32, RoleId)>> {
+        let mut redis_conn = self.conn.clone();
+        let mut rewards: Vec<(u32, RoleId)> = redis_conn
+            .hgetall::<_, Vec<(u32, u64)>>(rewards_key(guild_id))
+            .await?
+            .into_iter()
+            .map(|(level, role)| (level, RoleId(role)))
+            .collect();
+        rewards.sort_by_key(|(level, _)| *level);
+
+        Ok(rewards)
+    }
+
+    async fn set_reward(&self, guild_id: u64, level: u32, role: RoleId) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn
+            .hset(rewards_key(guild_id), level, role.0)
+            .await?)
+    }
+
+    async fn remove_reward(&self, guild_id: u64, level: u32) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn.hdel(rewards_key(guild_id), level).await?)
+    }
+
+    async fn get_xp_multipliers(&self, guild_id: u64) -> StorageResult<Vec<(XpTarget, f64)>> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn
+            .hgetall::<_, Vec<(String, f64)>>(multipliers_key(guild_id))
+            .await?
+            .into_iter()
+            .filter_map(|(key, multiplier)| Some((XpTarget::from_key(&key)?, multiplier)))
+            .collect())
+    }
+
+    async fn set_xp_multiplier(
+        &self,
+        guild_id: u64,
+        target: XpTarget,
+        multiplier: f64,
+    ) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn
+            .hset(multipliers_key(guild_id), target.key(), multiplier)
+            .await?)
+    }
+
+    async fn remove_xp_multiplier(&self, guild_id: u64, target: XpTarget) -> StorageResult<()> {
+        let mut redis_conn = self.conn.clone();
+        Ok(redis_conn
+            .hdel(multipliers_key(guild_id), target.key())
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys_are_scoped_to_guild() {
+        assert_eq!(level_key(1, 2, "exp"), "1:2:exp");
+        assert_ne!(level_key(1, 2, "exp"), level_key(3, 2, "exp"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Counting the days in a row a member has posted
+//!
+//! A streak moves on with the first XP-earning message on a new day, going by the day of the
+//! member's previous message. Missing more days than the grace period allows starts it over.
+use chrono::NaiveDate;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Streak {
+    /// Days in a row, as of the member's last message
+    pub current: u32,
+    /// The longest the streak has ever been
+    pub longest: u32,
+}
+
+impl Streak {
+    /// Whether a member who last posted on `last_day` has kept their streak going up to `today`
+    fn alive(last_day: NaiveDate, today: NaiveDate, grace_days: i64) -> bool {
+        (today - last_day).num_days() <= 1 + grace_days
+    }
+
+    /// Works out the streak after posting on `today`, having last posted on `last_day`
+    pub fn advance(&self, last_day: NaiveDate, today: NaiveDate, grace_days: i64) -> Streak {
+        let current = if self.current > 0 && last_day == today {
+            self.current
+        } else if self.current > 0 && Streak::alive(last_day, today, grace_days) {
+            self.current + 1
+        } else {
+            1
+        };
+
+        Streak {
+            current,
+            longest: self.longest.max(current),
+        }
+    }
+
+    /// Gets the streak as it stands on `today`, which is zero if it's run out
+    pub fn current_on(&self, last_day: NaiveDate, today: NaiveDate, grace_days: i64) -> u32 {
+        if Streak::alive(last_day, today, grace_days) {
+            self.current
+        } else {
+            0
+        }
+    }
+}
+
+/// Whether reaching `days` in a row earns a bonus, given a bonus every `milestone_days`
+pub fn is_milestone(days: u32, milestone_days: u32) -> bool {
+    days > 0 && days.checked_rem(milestone_days) == Some(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2021, 9, d).unwrap()
+    }
+
+    #[test]
+    fn consecutive_days_count_up() {
+        let streak = Streak::default().advance(day(1), day(1), 0);
+        assert_eq!(
+            streak,
+            Streak {
+                current: 1,
+                longest: 1
+            }
+        );
+        let streak = streak.advance(day(1), day(1), 0);
+        assert_eq!(streak.current, 1);
+        let streak = streak.advance(day(1), day(2), 0);
+        assert_eq!(
+            streak,
+            Streak {
+                current: 2,
+                longest: 2
+            }
+        );
+    }
+
+    #[test]
+    fn missed_days_reset_after_grace() {
+        let streak = Streak {
+            current: 5,
+            longest: 9,
+        };
+        assert_eq!(streak.advance(day(1), day(3), 1).current, 6);
+        assert_eq!(
+            streak.advance(day(1), day(3), 0),
+            Streak {
+                current: 1,
+                longest: 9
+            }
+        );
+        assert_eq!(streak.current_on(day(1), day(2), 0), 5);
+        assert_eq!(streak.current_on(day(1), day(3), 0), 0);
+    }
+
+    #[test]
+    fn milestones() {
+        assert!(is_milestone(7, 7));
+        assert!(is_milestone(14, 7));
+        assert!(!is_milestone(8, 7));
+        assert!(!is_milestone(7, 0));
+    }
+}
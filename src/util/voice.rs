@@ -0,0 +1,272 @@
+//! XP for time spent in voice channels
+//!
+//! Who is in which voice channel is kept up to date from `voice_state_update` events, and once a
+//! minute everyone talking with at least one other person earns a minute's worth of XP.
+use chrono::prelude::*;
+use serenity::{
+    client::Context,
+    model::{
+        guild::Guild,
+        id::{ChannelId, GuildId, RoleId, UserId},
+        voice::VoiceState,
+    },
+};
+use std::collections::{hash_map::Entry, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{error, instrument};
+
+use crate::util::data::{leveling_config, storage, voice_tracker};
+use crate::util::leveling::{announce_level_up, award_voice_xp};
+use crate::util::multipliers::{message_multiplier, XpTarget};
+use crate::util::rewards::sync_member_rewards;
+
+/// Someone sitting in a voice channel
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoiceMember {
+    pub channel_id: ChannelId,
+    pub bot: bool,
+    /// Deafened either by themselves or by a moderator
+    pub deafened: bool,
+    pub roles: Vec<RoleId>,
+}
+
+impl VoiceMember {
+    /// Reads a voice state, or `None` if it's someone leaving voice
+    pub fn from_state(state: &VoiceState) -> Option<VoiceMember> {
+        Some(VoiceMember {
+            channel_id: state.channel_id?,
+            bot: state
+                .member
+                .as_ref()
+                .map(|member| member.user.bot)
+                .unwrap_or(false),
+            deafened: state.deaf || state.self_deaf,
+            roles: state
+                .member
+                .as_ref()
+                .map(|member| member.roles.clone())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Whether this member counts towards anyone earning voice XP, themselves included
+    fn is_listening(&self) -> bool {
+        !self.bot && !self.deafened
+    }
+}
+
+/// Everyone currently in a voice channel, by guild
+#[derive(Debug, Default)]
+pub struct VoiceTracker {
+    guilds: HashMap<GuildId, HashMap<UserId, VoiceMember>>,
+}
+
+impl VoiceTracker {
+    /// Records where a member is now, or that they've left voice if `member` is `None`
+    pub fn update(&mut self, guild_id: GuildId, user_id: UserId, member: Option<VoiceMember>) {
+        let guild = self.guilds.entry(guild_id).or_default();
+        match member {
+            Some(member) => {
+                guild.insert(user_id, member);
+            }
+            None => {
+                guild.remove(&user_id);
+            }
+        }
+    }
+
+    /// Replaces everything known about a guild, such as when it becomes available
+    pub fn load_guild(&mut self, guild: &Guild) {
+        let members = guild
+            .voice_states
+            .iter()
+            .filter_map(|(user_id, state)| {
+                let mut member = VoiceMember::from_state(state)?;
+                // Voice states sent with the guild don't include the member
+                if let Some(guild_member) = guild.members.get(user_id) {
+                    member.bot = guild_member.user.bot;
+                    member.roles = guild_member.roles.clone();
+                }
+                Some((*user_id, member))
+            })
+            .collect();
+        self.guilds.insert(guild.id, members);
+    }
+
+    /// Everyone who should earn voice XP right now
+    ///
+    /// That's everyone who isn't a bot or deafened, in a channel with at least one other person
+    /// who isn't either.
+    pub fn earning_members(&self) -> Vec<(GuildId, UserId, VoiceMember)> {
+        let mut earning = Vec::new();
+        for (guild_id, members) in &self.guilds {
+            let mut listening: HashMap<ChannelId, usize> = HashMap::new();
+            for member in members.values().filter(|m| m.is_listening()) {
+                *listening.entry(member.channel_id).or_default() += 1;
+            }
+
+            for (user_id, member) in members {
+                if member.is_listening() && listening[&member.channel_id] >= 2 {
+                    earning.push((*guild_id, *user_id, member.clone()));
+                }
+            }
+        }
+
+        earning
+    }
+}
+
+/// Starts awarding voice XP once a minute, unless it's already been started
+///
+/// `ready` fires again after reconnecting, so `started` makes sure there's only ever one loop.
+pub fn start_voice_xp(ctx: Context, started: &AtomicBool) {
+    if started.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        // The first tick is immediate, and nobody has been anywhere for a minute yet
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            award_voice_minute(&ctx).await;
+        }
+    });
+}
+
+/// Gives everyone currently earning voice XP a minute's worth
+#[instrument(skip(ctx))]
+async fn award_voice_minute(ctx: &Context) {
+    let tracker = voice_tracker(ctx).await;
+    let earning = tracker.lock().unwrap().earning_members();
+    if earning.is_empty() {
+        return;
+    }
+
+    let storage = storage(ctx).await;
+    let config = leveling_config(ctx).await;
+    let now = Utc::now();
+    // Looked up once per guild, rather than once per member
+    let mut guild_multipliers: HashMap<GuildId, Option<Vec<(XpTarget, f64)>>> = HashMap::new();
+    for (guild_id, user_id, member) in earning {
+        let multipliers = match guild_multipliers.entry(guild_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(match storage.get_xp_multipliers(guild_id.0).await {
+                    Ok(multipliers) => Some(multipliers),
+                    Err(e) => {
+                        error!("Error getting XP multipliers: {:?}", e);
+                        None
+                    }
+                })
+            }
+        };
+
+        let multiplier = match multipliers {
+            Some(multipliers) => {
+                let category = ctx
+                    .cache
+                    .guild_channel(member.channel_id)
+                    .await
+                    .and_then(|channel| channel.category_id);
+                message_multiplier(multipliers, member.channel_id, category, &member.roles)
+            }
+            None => 1.0,
+        };
+
+        match award_voice_xp(
+            storage.as_ref(),
+            &config,
+            guild_id.0,
+            user_id.0,
+            1,
+            now,
+            multiplier,
+        )
+        .await
+        {
+            Ok(Some((old_data, new_data))) if new_data.level > old_data.level => {
+                if let Err(e) = announce_level_up(
+                    ctx,
+                    guild_id,
+                    user_id,
+                    None,
+                    old_data.level,
+                    new_data.level,
+                    storage.as_ref(),
+                )
+                .await
+                {
+                    error!("Error announcing level up: {:?}", e);
+                }
+                if let Err(e) =
+                    sync_member_rewards(ctx, guild_id, user_id.0, new_data.level, storage.as_ref())
+                        .await
+                {
+                    error!("Error syncing reward roles: {:?}", e);
+                }
+            }
+            Ok(_) => (),
+            Err(e) => error!("Error awarding voice XP: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GUILD: GuildId = GuildId(1);
+
+    fn member(channel: u64, bot: bool, deafened: bool) -> Option<VoiceMember> {
+        Some(VoiceMember {
+            channel_id: ChannelId(channel),
+            bot,
+            deafened,
+            roles: Vec::new(),
+        })
+    }
+
+    fn earning_ids(tracker: &VoiceTracker) -> Vec<u64> {
+        let mut ids: Vec<u64> = tracker
+            .earning_members()
+            .iter()
+            .map(|(_, user_id, _)| user_id.0)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn alone_earns_nothing() {
+        let mut tracker = VoiceTracker::default();
+        tracker.update(GUILD, UserId(1), member(10, false, false));
+        tracker.update(GUILD, UserId(2), member(11, false, false));
+        assert!(earning_ids(&tracker).is_empty());
+    }
+
+    #[test]
+    fn bots_and_deafened_members_dont_count() {
+        let mut tracker = VoiceTracker::default();
+        tracker.update(GUILD, UserId(1), member(10, false, false));
+        tracker.update(GUILD, UserId(2), member(10, true, false));
+        tracker.update(GUILD, UserId(3), member(10, false, true));
+        assert!(earning_ids(&tracker).is_empty());
+
+        tracker.update(GUILD, UserId(4), member(10, false, false));
+        assert_eq!(earning_ids(&tracker), vec![1, 4]);
+    }
+
+    #[test]
+    fn leaving_stops_earning() {
+        let mut tracker = VoiceTracker::default();
+        tracker.update(GUILD, UserId(1), member(10, false, false));
+        tracker.update(GUILD, UserId(2), member(10, false, false));
+        assert_eq!(earning_ids(&tracker), vec![1, 2]);
+
+        tracker.update(GUILD, UserId(2), None);
+        assert!(earning_ids(&tracker).is_empty());
+    }
+}